@@ -0,0 +1,443 @@
+// Clipboard persistence and history
+//
+// Whenever a client copies something, every MIME type it offers is read into
+// memory, up to `[clipboard] max_size` bytes in total (bigger copies aren't
+// kept). When that client exits and its selection goes away, the compositor
+// offers the saved copy itself, so it can still be pasted. The last
+// `[clipboard] history` copies, up to `max_history_size` bytes together, are
+// kept for the shell, which can list them and bring one back over IPC.
+//
+// Password managers mark their copies with `x-kde-passwordManagerHint`;
+// those are never read or kept.
+//
+// Only the clipboard is kept. The primary selection changes with every text
+// selection and is left alone. Copies made by X11 clients are bridged by the
+// X11 window manager and not saved.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::File,
+    io::{self, Read, Write},
+    os::fd::OwnedFd,
+    sync::Arc,
+};
+
+use smithay::{
+    input::Seat,
+    reexports::{
+        calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction},
+        wayland_server::DisplayHandle,
+    },
+    wayland::selection::{
+        data_device::{request_data_device_client_selection, set_data_device_selection, DataDeviceHandler},
+        SelectionHandler,
+    },
+};
+use tracing::{debug, info, warn};
+
+use crate::config::ClipboardConfig;
+
+/// Offered by password managers (KeePassXC, KDE Plasma) on copies of secrets
+const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+
+/// MIME types checked, in order, for a text version of a copy
+const TEXT_MIME_TYPES: &[&str] = &[
+    "text/plain;charset=utf-8",
+    "UTF8_STRING",
+    "text/plain",
+    "TEXT",
+    "STRING",
+];
+
+/// Everything one copy offered, by MIME type
+#[derive(Debug, Default)]
+pub struct ClipboardContents {
+    data: Vec<(String, Vec<u8>)>,
+}
+
+impl ClipboardContents {
+    pub fn mime_types(&self) -> Vec<String> {
+        self.data.iter().map(|(mime_type, _)| mime_type.clone()).collect()
+    }
+
+    pub fn get(&self, mime_type: &str) -> Option<&[u8]> {
+        self.data
+            .iter()
+            .find(|(offered, _)| offered == mime_type)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Total bytes over all MIME types
+    pub fn size(&self) -> usize {
+        self.data.iter().map(|(_, data)| data.len()).sum()
+    }
+
+    /// The start of the copy as text, at most `max_chars` long, if it offered
+    /// a text type
+    pub fn text_preview(&self, max_chars: usize) -> Option<String> {
+        TEXT_MIME_TYPES
+            .iter()
+            .find_map(|mime_type| self.get(mime_type))
+            .map(|data| {
+                // Enough bytes for `max_chars` characters of UTF-8
+                let data = &data[..data.len().min(max_chars * 4)];
+                String::from_utf8_lossy(data).chars().take(max_chars).collect()
+            })
+    }
+}
+
+/// What answers pastes from a selection the compositor offers itself
+#[derive(Debug, Clone)]
+pub enum OfferedSelection {
+    /// A saved copy
+    Saved(Arc<ClipboardContents>),
+    /// An X11 client's selection, read through the X11 window manager
+    Xwayland,
+}
+
+pub trait ClipboardHandler:
+    SelectionHandler<SelectionUserData = OfferedSelection> + DataDeviceHandler + Sized + 'static
+{
+    fn clipboard(&mut self) -> &mut Clipboard<Self>;
+
+    /// A copy was saved and added to the history
+    fn clipboard_saved(&mut self) {}
+
+    /// A saved copy became the current clipboard
+    fn saved_clipboard_offered(&mut self, _mime_types: Vec<String>) {}
+}
+
+/// A copy being read from its source client
+struct Capture<D: ClipboardHandler> {
+    id: u64,
+    seat: Seat<D>,
+    pending: usize,  // MIME types still being read
+    contents: ClipboardContents,
+    source_gone: bool,  // The selection went away before we finished reading
+}
+
+pub struct Clipboard<D: ClipboardHandler> {
+    loop_handle: LoopHandle<'static, D>,
+    display_handle: DisplayHandle,
+    max_size: usize,
+    history_size: usize,
+    max_history_bytes: usize,
+    history: VecDeque<Arc<ClipboardContents>>,  // Newest first
+    capture: Option<Capture<D>>,
+    next_capture_id: u64,
+    current_saved: bool,  // `history[0]` is the current selection
+}
+
+impl<D: ClipboardHandler> Clipboard<D> {
+    pub fn new(loop_handle: LoopHandle<'static, D>, display_handle: DisplayHandle, config: &ClipboardConfig) -> Self {
+        Self {
+            loop_handle,
+            display_handle,
+            max_size: config.max_size,
+            history_size: config.history,
+            max_history_bytes: config.max_history_size,
+            history: VecDeque::new(),
+            capture: None,
+            next_capture_id: 0,
+            current_saved: false,
+        }
+    }
+
+    /// Apply a reloaded config
+    pub fn set_config(&mut self, config: &ClipboardConfig) {
+        self.max_size = config.max_size;
+        self.history_size = config.history;
+        self.max_history_bytes = config.max_history_size;
+        self.trim_history();
+    }
+
+    /// Drop the oldest copies beyond the configured count or total size. The
+    /// newest copy always stays, as it may be the current clipboard.
+    fn trim_history(&mut self) {
+        trim_history(&mut self.history, self.history_size, self.max_history_bytes);
+    }
+
+    /// Saved copies, newest first
+    pub fn history(&self) -> impl Iterator<Item = &Arc<ClipboardContents>> {
+        self.history.iter()
+    }
+
+    /// The saved copy that is the current clipboard, if any
+    pub fn current(&self) -> Option<&Arc<ClipboardContents>> {
+        self.history.front().filter(|_| self.current_saved)
+    }
+
+    /// The clipboard now belongs to someone whose copies aren't saved
+    pub fn forget_current(&mut self) {
+        self.capture = None;
+        self.current_saved = false;
+    }
+}
+
+/// Drop the oldest of `history` (newest first) until at most `max_count`
+/// copies of at most `max_bytes` together are left, but never the newest
+fn trim_history(history: &mut VecDeque<Arc<ClipboardContents>>, max_count: usize, max_bytes: usize) {
+    history.truncate(max_count.max(1));
+    let mut total: usize = history.iter().map(|contents| contents.size()).sum();
+    while history.len() > 1 && total > max_bytes {
+        if let Some(oldest) = history.pop_back() {
+            total -= oldest.size();
+        }
+    }
+}
+
+/// Track a clipboard change from `SelectionHandler::new_selection`: start
+/// saving a new copy, or offer the saved one if the selection went away
+pub fn selection_changed<D: ClipboardHandler>(state: &mut D, seat: &Seat<D>, mime_types: Option<Vec<String>>) {
+    let clipboard = state.clipboard();
+    let seat = seat.clone();
+    // The seat's selection is only updated after the handler returns
+    match mime_types {
+        Some(mime_types) => {
+            clipboard.capture = None;
+            clipboard.current_saved = false;
+            clipboard.loop_handle.insert_idle(move |state| start_capture(state, &seat, mime_types));
+        }
+        None => {
+            if let Some(capture) = &mut clipboard.capture {
+                capture.source_gone = true;
+            } else if clipboard.current_saved {
+                clipboard.loop_handle.insert_idle(move |state| {
+                    // Unless something else was copied in the meantime
+                    if state.clipboard().current_saved {
+                        offer_saved(state, &seat);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Make history entry `index` the current clipboard. Returns false if there
+/// is no such entry.
+pub fn restore<D: ClipboardHandler>(state: &mut D, seat: &Seat<D>, index: usize) -> bool {
+    let clipboard = state.clipboard();
+    let Some(contents) = clipboard.history.remove(index) else {
+        return false;
+    };
+    clipboard.history.push_front(contents);
+    clipboard.capture = None;
+    clipboard.current_saved = true;
+    offer_saved(state, seat);
+    true
+}
+
+/// Answer a paste from a compositor-owned selection (`SelectionHandler::send_selection`)
+pub fn send(contents: &Arc<ClipboardContents>, mime_type: &str, fd: OwnedFd) {
+    let contents = contents.clone();
+    let mime_type = mime_type.to_string();
+    // A slow reader must not block the compositor
+    std::thread::spawn(move || {
+        let Some(data) = contents.get(&mime_type) else {
+            return;
+        };
+        if let Err(err) = File::from(fd).write_all(data) {
+            debug!("Paste of {:?} not completed: {}", mime_type, err);
+        }
+    });
+}
+
+fn offer_saved<D: ClipboardHandler>(state: &mut D, seat: &Seat<D>) {
+    let clipboard = state.clipboard();
+    let Some(contents) = clipboard.history.front().cloned() else {
+        return;
+    };
+    debug!("Offering saved clipboard: {:?}", contents.mime_types());
+    let display_handle = clipboard.display_handle.clone();
+    let mime_types = contents.mime_types();
+    set_data_device_selection(&display_handle, seat, mime_types.clone(), OfferedSelection::Saved(contents));
+    state.saved_clipboard_offered(mime_types);
+}
+
+fn start_capture<D: ClipboardHandler>(state: &mut D, seat: &Seat<D>, mut mime_types: Vec<String>) {
+    if mime_types.iter().any(|mime_type| mime_type == PASSWORD_MANAGER_HINT) {
+        debug!("📋 Not saving clipboard: marked as a secret by a password manager");
+        return;
+    }
+    // Each MIME type is read once, wherever its duplicates are in the offer
+    let mut seen = HashSet::new();
+    mime_types.retain(|mime_type| seen.insert(mime_type.clone()));
+    let clipboard = state.clipboard();
+    let id = clipboard.next_capture_id;
+    clipboard.next_capture_id += 1;
+    clipboard.capture = Some(Capture {
+        id,
+        seat: seat.clone(),
+        pending: 0,
+        contents: ClipboardContents::default(),
+        source_gone: false,
+    });
+
+    for mime_type in mime_types {
+        let (reader, writer) = match io::pipe() {
+            Ok(pipe) => pipe,
+            Err(err) => {
+                warn!("Can't save clipboard: {}", err);
+                break;
+            }
+        };
+        if let Err(err) = request_data_device_client_selection(seat, mime_type.clone(), OwnedFd::from(writer)) {
+            debug!("Can't read clipboard as {:?}: {:?}", mime_type, err);
+            continue;
+        }
+
+        let reader = File::from(OwnedFd::from(reader));
+        let mut data = Vec::new();
+        let inserted = clipboard.loop_handle.insert_source(
+            Generic::new(reader, Interest::READ, Mode::Level),
+            move |_, reader, state: &mut D| {
+                let mut chunk = [0u8; 64 * 1024];
+                // One read per wakeup, as the pipe blocks
+                let read = (&**reader).read(&mut chunk);
+                let clipboard = state.clipboard();
+                let Some(capture) = clipboard.capture.as_mut().filter(|capture| capture.id == id) else {
+                    return Ok(PostAction::Remove);
+                };
+                match read {
+                    Ok(0) => {
+                        capture.contents.data.push((mime_type.clone(), std::mem::take(&mut data)));
+                        capture.pending -= 1;
+                        if capture.pending == 0 {
+                            finish_capture(state);
+                        }
+                        Ok(PostAction::Remove)
+                    }
+                    Ok(n) => {
+                        data.extend_from_slice(&chunk[..n]);
+                        let size = capture.contents.size() + data.len();
+                        if size > clipboard.max_size {
+                            info!("📋 Not saving clipboard: larger than {} bytes", clipboard.max_size);
+                            clipboard.capture = None;
+                            return Ok(PostAction::Remove);
+                        }
+                        Ok(PostAction::Continue)
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => Ok(PostAction::Continue),
+                    Err(err) => {
+                        debug!("Failed to read clipboard as {:?}: {}", mime_type, err);
+                        capture.pending -= 1;
+                        if capture.pending == 0 {
+                            finish_capture(state);
+                        }
+                        Ok(PostAction::Remove)
+                    }
+                }
+            },
+        );
+        match inserted {
+            Ok(_) => {
+                if let Some(capture) = &mut clipboard.capture {
+                    capture.pending += 1;
+                }
+            }
+            Err(err) => warn!("Failed to watch clipboard transfer: {}", err.error),
+        }
+    }
+
+    if clipboard.capture.as_ref().is_some_and(|capture| capture.pending == 0) {
+        clipboard.capture = None;
+    }
+}
+
+/// All MIME types of the current copy have been read
+fn finish_capture<D: ClipboardHandler>(state: &mut D) {
+    let clipboard = state.clipboard();
+    let Some(capture) = clipboard.capture.take() else {
+        return;
+    };
+    debug!(
+        "📋 Saved clipboard: {:?} ({} bytes)",
+        capture.contents.mime_types(),
+        capture.contents.size()
+    );
+    clipboard.history.push_front(Arc::new(capture.contents));
+    clipboard.trim_history();
+    clipboard.current_saved = true;
+
+    // The source exited while we were reading; offer what we got
+    if capture.source_gone {
+        offer_saved(state, &capture.seat);
+    }
+    state.clipboard_saved();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(data: &[(&str, &[u8])]) -> Arc<ClipboardContents> {
+        Arc::new(ClipboardContents {
+            data: data
+                .iter()
+                .map(|(mime_type, data)| (mime_type.to_string(), data.to_vec()))
+                .collect(),
+        })
+    }
+
+    /// History of copies with the given sizes, newest first
+    fn history(sizes: &[usize]) -> VecDeque<Arc<ClipboardContents>> {
+        sizes
+            .iter()
+            .map(|&size| contents(&[("text/plain", &vec![b'x'; size])]))
+            .collect()
+    }
+
+    fn sizes(history: &VecDeque<Arc<ClipboardContents>>) -> Vec<usize> {
+        history.iter().map(|contents| contents.size()).collect()
+    }
+
+    #[test]
+    fn trim_history_keeps_newest_copies() {
+        let mut copies = history(&[1, 2, 3, 4]);
+        trim_history(&mut copies, 2, 1000);
+        assert_eq!(sizes(&copies), [1, 2]);
+
+        // Byte limit: drop from the old end until the rest fits
+        let mut copies = history(&[10, 20, 30, 40]);
+        trim_history(&mut copies, 10, 60);
+        assert_eq!(sizes(&copies), [10, 20, 30]);
+        let mut copies = history(&[10, 20, 30, 40]);
+        trim_history(&mut copies, 10, 59);
+        assert_eq!(sizes(&copies), [10, 20]);
+    }
+
+    #[test]
+    fn trim_history_never_drops_the_newest_copy() {
+        let mut copies = history(&[100, 1]);
+        trim_history(&mut copies, 5, 10);
+        assert_eq!(sizes(&copies), [100]);
+
+        let mut copies = history(&[1, 2]);
+        trim_history(&mut copies, 0, 1000);
+        assert_eq!(sizes(&copies), [1]);
+    }
+
+    #[test]
+    fn text_preview_prefers_utf8_text() {
+        let copy = contents(&[
+            ("text/html", b"<b>bold</b>"),
+            ("STRING", b"latin"),
+            ("text/plain;charset=utf-8", "h\u{e9}llo w\u{f6}rld".as_bytes()),
+        ]);
+        assert_eq!(copy.text_preview(100).as_deref(), Some("h\u{e9}llo w\u{f6}rld"));
+        assert_eq!(copy.text_preview(5).as_deref(), Some("h\u{e9}llo"));
+
+        assert_eq!(contents(&[("TEXT", b"plain")]).text_preview(10).as_deref(), Some("plain"));
+        assert_eq!(contents(&[("image/png", b"\x89PNG")]).text_preview(10), None);
+    }
+
+    #[test]
+    fn text_preview_of_truncated_or_invalid_utf8() {
+        // Cut inside a multi-byte character, and a stray invalid byte
+        let copy = contents(&[("text/plain", "a\u{1f426}\u{1f426}".as_bytes())]);
+        assert_eq!(copy.text_preview(2).as_deref(), Some("a\u{1f426}"));
+
+        let copy = contents(&[("text/plain", b"ok\xffok")]);
+        assert_eq!(copy.text_preview(10).as_deref(), Some("ok\u{fffd}ok"));
+    }
+}
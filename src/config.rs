@@ -0,0 +1,406 @@
+// User configuration
+//
+// Loaded from `$XDG_CONFIG_HOME/nuthatch/config.toml` (or `~/.config/...`).
+// Every section is optional; a missing or invalid file gives the defaults.
+//
+// Example:
+//
+//     [outputs]
+//     auto_scale = true
+//
+//     [outputs.scale]
+//     "eDP-1" = 1.5                  # by connector name
+//     "DEL-A0C4-1234" = 1.25         # or by EDID identity
+//
+//     [gestures]
+//     snap_fingers = 4       # swipe left/right: snap the focused window
+//
+//     [touch]
+//     output = "eDP-1"       # output for touchscreens (default: the built-in panel)
+//
+//     [touch.devices]
+//     "Wacom HID 52C2 Finger" = "DP-1"  # per device, by libinput device name
+//
+//     [tablet]
+//     output = "DP-1"        # output for tablets (default: span all outputs)
+//
+//     [tablet.devices]
+//     "Wacom Intuos Pro M Pen" = "eDP-1"
+//
+//     [input.touchpad]       # all touchpads; [input.mouse] for other pointers
+//     tap = true
+//     natural_scroll = true
+//     click_method = "clickfinger"
+//
+//     [input.devices."Logitech MX Master 3"]
+//     accel_profile = "flat"
+//     accel_speed = -0.2
+//
+//     [keyboard]
+//     layout = "us,de"
+//     variant = ",nodeadkeys"
+//     options = "compose:ralt"
+//     switch_layout = "Super+space"   # cycles through `layout`
+//     # keymap_file = "custom.xkb"    # instead of the XKB names above
+//
+//     [clipboard]
+//     max_size = 8388608     # bytes; bigger copies aren't kept after the app exits
+//     history = 20           # copies the shell can list and restore over IPC
+//     max_history_size = 67108864  # bytes over all copies; the oldest go first
+//
+// Send SIGHUP to reload the config.
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use serde::Deserialize;
+use smithay::input::keyboard::{xkb, Keysym, ModifiersState};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub outputs: OutputsConfig,
+    pub gestures: GesturesConfig,
+    pub touch: TouchConfig,
+    pub tablet: TabletConfig,
+    pub input: InputConfig,
+    pub keyboard: KeyboardConfig,
+    pub clipboard: ClipboardConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OutputsConfig {
+    /// Pick a default scale from the monitor's DPI for monitors without a saved layout
+    pub auto_scale: bool,
+    /// Scale overrides, keyed by connector name or EDID identity
+    pub scale: HashMap<String, f64>,
+}
+
+impl Default for OutputsConfig {
+    fn default() -> Self {
+        Self {
+            auto_scale: true,
+            scale: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GesturesConfig {
+    /// Fingers for snapping windows to half the output; 0 disables
+    pub snap_fingers: u32,
+}
+
+impl Default for GesturesConfig {
+    fn default() -> Self {
+        Self {
+            snap_fingers: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TouchConfig {
+    /// Output (connector name or EDID identity) touchscreens are mapped to
+    pub output: Option<String>,
+    /// Per-device overrides of `output`, keyed by device name
+    pub devices: HashMap<String, String>,
+}
+
+impl TouchConfig {
+    /// Configured output for a touch device, if any
+    pub fn output_for(&self, device: &str) -> Option<&str> {
+        self.devices.get(device).or(self.output.as_ref()).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TabletConfig {
+    /// Output (connector name or EDID identity) tablets are mapped to
+    pub output: Option<String>,
+    /// Per-device overrides of `output`, keyed by device name
+    pub devices: HashMap<String, String>,
+}
+
+impl TabletConfig {
+    /// Configured output for a tablet, if any
+    pub fn output_for(&self, device: &str) -> Option<&str> {
+        self.devices.get(device).or(self.output.as_ref()).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    pub touchpad: DeviceSettings,
+    /// Mice, trackballs and trackpoints
+    pub mouse: DeviceSettings,
+    /// Per-device overrides, keyed by device name
+    pub devices: HashMap<String, DeviceSettings>,
+}
+
+/// libinput settings; anything unset keeps the device's default
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DeviceSettings {
+    pub tap: Option<bool>,
+    pub natural_scroll: Option<bool>,
+    pub accel_profile: Option<AccelProfileSetting>,
+    /// From -1.0 (slowest) to 1.0 (fastest)
+    pub accel_speed: Option<f64>,
+    pub disable_while_typing: Option<bool>,
+    pub click_method: Option<ClickMethodSetting>,
+    pub scroll_method: Option<ScrollMethodSetting>,
+    pub left_handed: Option<bool>,
+    pub middle_emulation: Option<bool>,
+}
+
+impl DeviceSettings {
+    /// These settings, with unset fields taken from `fallback`
+    pub fn or(&self, fallback: &DeviceSettings) -> DeviceSettings {
+        DeviceSettings {
+            tap: self.tap.or(fallback.tap),
+            natural_scroll: self.natural_scroll.or(fallback.natural_scroll),
+            accel_profile: self.accel_profile.or(fallback.accel_profile),
+            accel_speed: self.accel_speed.or(fallback.accel_speed),
+            disable_while_typing: self.disable_while_typing.or(fallback.disable_while_typing),
+            click_method: self.click_method.or(fallback.click_method),
+            scroll_method: self.scroll_method.or(fallback.scroll_method),
+            left_handed: self.left_handed.or(fallback.left_handed),
+            middle_emulation: self.middle_emulation.or(fallback.middle_emulation),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccelProfileSetting {
+    Flat,
+    Adaptive,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClickMethodSetting {
+    ButtonAreas,
+    Clickfinger,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScrollMethodSetting {
+    None,
+    TwoFinger,
+    Edge,
+    OnButtonDown,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KeyboardConfig {
+    pub rules: String,
+    pub model: String,
+    /// Comma-separated list of layouts, e.g. "us,de"
+    pub layout: String,
+    pub variant: String,
+    pub options: Option<String>,
+    /// Compiled XKB keymap used instead of the names above; relative paths
+    /// are resolved against the config directory
+    pub keymap_file: Option<PathBuf>,
+    /// Milliseconds before a held key starts repeating
+    pub repeat_delay: i32,
+    /// Repeats per second
+    pub repeat_rate: i32,
+    /// Key combination that cycles through the configured layouts
+    pub switch_layout: Option<String>,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        Self {
+            rules: String::new(),
+            model: String::new(),
+            layout: String::new(),
+            variant: String::new(),
+            options: None,
+            keymap_file: None,
+            repeat_delay: 200,
+            repeat_rate: 25,
+            switch_layout: Some("Super+space".into()),
+        }
+    }
+}
+
+impl KeyboardConfig {
+    /// Contents of `keymap_file`, if one is configured and readable
+    pub fn keymap(&self) -> Option<String> {
+        let path = self.keymap_file.as_ref()?;
+        let path = match config_file_path().and_then(|file| file.parent().map(PathBuf::from)) {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path.clone(),
+        };
+        match fs::read_to_string(&path) {
+            Ok(keymap) => Some(keymap),
+            Err(err) => {
+                warn!("Can't read keymap {:?}, using XKB names instead: {}", path, err);
+                None
+            }
+        }
+    }
+
+    pub fn switch_layout_binding(&self) -> Option<KeyBinding> {
+        let binding = self.switch_layout.as_deref()?;
+        let parsed = KeyBinding::parse(binding);
+        if parsed.is_none() {
+            warn!("Invalid switch_layout binding {:?}", binding);
+        }
+        parsed
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    /// Most bytes saved per copy, over all of its MIME types
+    pub max_size: usize,
+    /// Number of copies kept in the history
+    pub history: usize,
+    /// Most bytes kept over all copies in the history
+    pub max_history_size: usize,
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 8 * 1024 * 1024,
+            history: 20,
+            max_history_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// A key combination like "Ctrl+Alt+t"; the key is matched by its keysym on
+/// the first (usually latin) layout, so bindings work on any layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+    pub keysym: Keysym,
+}
+
+impl KeyBinding {
+    pub fn parse(binding: &str) -> Option<Self> {
+        let mut parts: Vec<&str> = binding.split('+').map(str::trim).collect();
+        let key = parts.pop()?;
+        let keysym = xkb::keysym_from_name(key, xkb::KEYSYM_CASE_INSENSITIVE);
+        if keysym.raw() == xkb::keysyms::KEY_NoSymbol {
+            return None;
+        }
+
+        let mut parsed = Self {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            logo: false,
+            keysym,
+        };
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => parsed.ctrl = true,
+                "alt" => parsed.alt = true,
+                "shift" => parsed.shift = true,
+                "super" | "logo" | "mod4" => parsed.logo = true,
+                _ => return None,
+            }
+        }
+        Some(parsed)
+    }
+
+    pub fn matches(&self, modifiers: &ModifiersState, keysym: Option<Keysym>) -> bool {
+        modifiers.ctrl == self.ctrl
+            && modifiers.alt == self.alt
+            && modifiers.shift == self.shift
+            && modifiers.logo == self.logo
+            && keysym == Some(self.keysym)
+    }
+}
+
+impl OutputsConfig {
+    /// Configured scale for an output, matching the EDID identity first
+    pub fn scale_for(&self, identity: &str, connector: &str) -> Option<f64> {
+        self.scale
+            .get(identity)
+            .or_else(|| self.scale.get(connector))
+            .copied()
+            .filter(|scale| scale.is_finite() && *scale > 0.0)
+    }
+}
+
+impl Config {
+    /// Load the config file, falling back to defaults if it is missing or invalid
+    pub fn load() -> Self {
+        let Some(path) = config_file_path() else {
+            return Self::default();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => match toml::from_str(&contents) {
+                Ok(config) => {
+                    info!("Loaded config from {:?}", path);
+                    config
+                }
+                Err(err) => {
+                    warn!("Invalid config in {:?}, using defaults: {}", path, err);
+                    Self::default()
+                }
+            },
+            Err(_) => {
+                info!("No config at {:?}, using defaults", path);
+                Self::default()
+            }
+        }
+    }
+}
+
+pub fn config_file_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("nuthatch").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_binding_modifiers() {
+        let binding = KeyBinding::parse("Super+Shift+Return").unwrap();
+        assert!(binding.logo && binding.shift);
+        assert!(!binding.ctrl && !binding.alt);
+        assert_eq!(binding.keysym, Keysym::Return);
+
+        // Modifier names are case-insensitive and have aliases
+        let binding = KeyBinding::parse("control + ALT + t").unwrap();
+        assert!(binding.ctrl && binding.alt);
+        assert!(!binding.logo && !binding.shift);
+        assert!(KeyBinding::parse("Mod4+q").unwrap().logo);
+
+        let binding = KeyBinding::parse("F1").unwrap();
+        assert!(!binding.ctrl && !binding.alt && !binding.shift && !binding.logo);
+    }
+
+    #[test]
+    fn key_binding_rejects_unknown_names() {
+        assert!(KeyBinding::parse("Hyper+a").is_none());
+        assert!(KeyBinding::parse("Super+NotAKey").is_none());
+        assert!(KeyBinding::parse("").is_none());
+    }
+}
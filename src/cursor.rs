@@ -1,5 +1,11 @@
-use std::{io::Read, time::Duration};
-use tracing::warn;
+use std::{collections::HashMap, io::Read, time::Duration};
+use smithay::{
+    input::pointer::{CursorIcon, CursorImageSurfaceData},
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Point},
+    wayland::compositor::with_states,
+};
+use tracing::{debug, warn};
 use xcursor::{
     parser::{parse_xcursor, Image},
     CursorTheme,
@@ -7,9 +13,12 @@ use xcursor::{
 
 static FALLBACK_CURSOR_DATA: &[u8] = include_bytes!("../resources/cursor.rgba");
 
+/// Named cursor images from the xcursor theme, loaded the first time each
+/// shape is needed at a given size
 pub struct Cursor {
-    icons: Vec<Image>,
+    theme: CursorTheme,
     size: u32,
+    cache: HashMap<(&'static str, u32), Vec<Image>>,
 }
 
 impl Cursor {
@@ -22,31 +31,75 @@ impl Cursor {
             .and_then(|s| s.parse().ok())
             .unwrap_or(24);
 
+        // Resolves the theme's `Inherits` chain, so icons missing from the
+        // theme itself are looked up in the themes it builds on
         let theme = CursorTheme::load(&name);
-        let icons = load_icon(&theme)
-            .map_err(|err| warn!("Unable to load xcursor: {}, using fallback cursor", err))
-            .unwrap_or_else(|_| {
-                vec![Image {
-                    size: 32,
-                    width: 64,
-                    height: 64,
-                    xhot: 1,
-                    yhot: 1,
-                    delay: 1,
-                    pixels_rgba: Vec::from(FALLBACK_CURSOR_DATA),
-                    pixels_argb: vec![], //unused
-                }]
-            });
-
-        Cursor { icons, size }
+
+        Cursor {
+            theme,
+            size,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Nominal size of cursors at scale 1 (`XCURSOR_SIZE`)
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Frame of `icon`'s animation showing at `time`, with its index among
+    /// the frames. Static cursors always return frame 0.
+    pub fn get_image(&mut self, icon: CursorIcon, scale: u32, time: Duration) -> (usize, &Image) {
+        let images = self.images(icon, scale);
+        let index = frame_at(images, time).0;
+        (index, &images[index])
+    }
+
+    /// How long until `icon` shows its next animation frame, or None if it
+    /// isn't animated
+    pub fn next_frame_in(&mut self, icon: CursorIcon, scale: u32, time: Duration) -> Option<Duration> {
+        frame_at(self.images(icon, scale), time).1
     }
 
-    pub fn get_image(&self, scale: u32, _time: Duration) -> &Image {
+    fn images(&mut self, icon: CursorIcon, scale: u32) -> &[Image] {
         let size = self.size * scale;
-        nearest_images(size, &self.icons).next().unwrap()
+        let theme = &self.theme;
+        self.cache
+            .entry((icon.name(), size))
+            .or_insert_with(|| load_images(theme, icon, size))
     }
 }
 
+/// Index of the frame showing at `time` into a looping animation and the
+/// time left until it changes. Frames last `delay` milliseconds each.
+fn frame_at(images: &[Image], time: Duration) -> (usize, Option<Duration>) {
+    let total: u64 = images.iter().map(|image| image.delay as u64).sum();
+    if images.len() < 2 || total == 0 {
+        return (0, None);
+    }
+
+    let mut elapsed = time.as_millis() as u64 % total;
+    for (index, image) in images.iter().enumerate() {
+        let delay = image.delay as u64;
+        if elapsed < delay {
+            return (index, Some(Duration::from_millis(delay - elapsed)));
+        }
+        elapsed -= delay;
+    }
+    (0, None)
+}
+
+/// Hotspot a client set for its cursor surface with wl_pointer.set_cursor
+pub fn surface_hotspot(surface: &WlSurface) -> Point<i32, Logical> {
+    with_states(surface, |states| {
+        states
+            .data_map
+            .get::<CursorImageSurfaceData>()
+            .map(|data| data.lock().unwrap().hotspot)
+    })
+    .unwrap_or_default()
+}
+
 fn nearest_images(size: u32, images: &[Image]) -> impl Iterator<Item = &Image> {
     let nearest_image = images
         .iter()
@@ -60,18 +113,129 @@ fn nearest_images(size: u32, images: &[Image]) -> impl Iterator<Item = &Image> {
 
 #[derive(thiserror::Error, Debug)]
 enum Error {
-    #[error("Theme has no default cursor")]
-    NoDefaultCursor,
+    #[error("Theme has no {0:?} cursor")]
+    NoCursor(&'static str),
     #[error("Error opening xcursor file: {0}")]
     File(#[from] std::io::Error),
     #[error("Failed to parse XCursor file")]
     Parse,
 }
 
-fn load_icon(theme: &CursorTheme) -> Result<Vec<Image>, Error> {
-    let icon_path = theme.load_icon("default").ok_or(Error::NoDefaultCursor)?;
+/// Images for `icon` at the size nearest to `size`: the icon itself or one
+/// of its legacy names, else the theme's default arrow, else our embedded one
+fn load_images(theme: &CursorTheme, icon: CursorIcon, size: u32) -> Vec<Image> {
+    let names = std::iter::once(icon.name())
+        .chain(icon.alt_names().iter().copied())
+        .chain(std::iter::once(CursorIcon::Default.name()));
+
+    let mut last_error = None;
+    for name in names {
+        match load_icon(theme, name) {
+            Ok(images) if !images.is_empty() => {
+                if name != icon.name() {
+                    debug!("Cursor {:?} not in theme, using {:?}", icon.name(), name);
+                }
+                return nearest_images(size, &images).cloned().collect();
+            }
+            Ok(_) => last_error = Some(Error::Parse),
+            Err(err) => last_error = Some(err),
+        }
+    }
+
+    if let Some(err) = last_error {
+        warn!("Unable to load xcursor {:?}: {}, using fallback cursor", icon.name(), err);
+    }
+    vec![fallback_image()]
+}
+
+/// Our embedded arrow, a single 64x64 image
+fn fallback_image() -> Image {
+    Image {
+        size: 64,
+        width: 64,
+        height: 64,
+        xhot: 1,
+        yhot: 1,
+        delay: 1,
+        pixels_rgba: Vec::from(FALLBACK_CURSOR_DATA),
+        pixels_argb: vec![], //unused
+    }
+}
+
+/// Buffer scale to draw `image` with so it keeps the logical size of a
+/// `base_size` cursor. Themes without the requested size give us their
+/// nearest one, which has to be drawn at its own scale.
+pub fn buffer_scale(image: &Image, base_size: u32) -> i32 {
+    ((image.size as f64 / base_size as f64).round() as i32).max(1)
+}
+
+fn load_icon(theme: &CursorTheme, name: &'static str) -> Result<Vec<Image>, Error> {
+    let icon_path = theme.load_icon(name).ok_or(Error::NoCursor(name))?;
     let mut cursor_file = std::fs::File::open(icon_path)?;
     let mut cursor_data = Vec::new();
     cursor_file.read_to_end(&mut cursor_data)?;
     parse_xcursor(&cursor_data).ok_or(Error::Parse)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(delays: &[u32]) -> Vec<Image> {
+        delays
+            .iter()
+            .map(|&delay| Image {
+                size: 24,
+                width: 24,
+                height: 24,
+                xhot: 0,
+                yhot: 0,
+                delay,
+                pixels_rgba: vec![],
+                pixels_argb: vec![],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frame_at_wraps_around() {
+        let images = frames(&[100, 50, 150]);
+        let ms = Duration::from_millis;
+        assert_eq!(frame_at(&images, ms(0)), (0, Some(ms(100))));
+        assert_eq!(frame_at(&images, ms(120)), (1, Some(ms(30))));
+        assert_eq!(frame_at(&images, ms(299)), (2, Some(ms(1))));
+        // One full loop is 300ms
+        assert_eq!(frame_at(&images, ms(300)), (0, Some(ms(100))));
+        assert_eq!(frame_at(&images, ms(3 * 300 + 160)), (2, Some(ms(140))));
+    }
+
+    #[test]
+    fn buffer_scale_follows_nominal_size() {
+        let image = |size| Image {
+            size,
+            ..frames(&[0]).remove(0)
+        };
+        assert_eq!(buffer_scale(&image(24), 24), 1);
+        assert_eq!(buffer_scale(&image(48), 24), 2);
+        // A theme's nearest size to a 2x request for a 32px cursor
+        assert_eq!(buffer_scale(&image(72), 32), 2);
+        assert_eq!(buffer_scale(&image(16), 24), 1);
+    }
+
+    #[test]
+    fn fallback_cursor_keeps_nominal_size() {
+        let image = fallback_image();
+        assert_eq!(image.pixels_rgba.len(), (image.width * image.height * 4) as usize);
+        // Drawn at 2x for a 32px cursor, so it is 32 logical pixels wide
+        let scale = buffer_scale(&image, 32);
+        assert_eq!(scale, 2);
+        assert_eq!(image.width as i32 / scale, 32);
+        assert_eq!(buffer_scale(&image, 64), 1);
+    }
+
+    #[test]
+    fn frame_at_static_cursor() {
+        assert_eq!(frame_at(&frames(&[50]), Duration::from_secs(7)), (0, None));
+        assert_eq!(frame_at(&frames(&[0, 0]), Duration::from_secs(7)), (0, None));
+    }
+}
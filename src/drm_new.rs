@@ -10,8 +10,14 @@
 // and will customize the window management behavior for Nuthatch's unique UX.
 
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+    os::{fd::OwnedFd, unix::net::UnixStream},
     path::Path,
+    process::{Command, Stdio},
+    sync::Arc,
     time::Duration,
 };
 
@@ -25,21 +31,25 @@ use smithay::{
         },
         drm::{
             compositor::DrmCompositor,
-            CreateDrmNodeError, DrmDevice, DrmDeviceFd, DrmEvent, DrmNode, NodeType,
+            CreateDrmNodeError, DrmDevice, DrmDeviceFd, DrmEvent, DrmEventMetadata, DrmEventTime,
+            DrmNode, NodeType,
             exporter::gbm::GbmFramebufferExporter,
             output::{DrmOutput, DrmOutputManager, DrmOutputRenderElements},
         },
         renderer::element::{
-            RenderElement,
+            default_primary_scanout_output_compare,
+            Element, Id, RenderElementPresentationState, RenderElementStates,
             memory::MemoryRenderBufferRenderElement,
+            surface::{render_elements_from_surface_tree, WaylandSurfaceRenderElement},
         },
         egl::{EGLContext, EGLDevice, EGLDisplay},
-        input::InputEvent,
+        input::{InputEvent, TabletToolDescriptor, TouchSlot},
         libinput::{LibinputInputBackend, LibinputSessionInterface},
         renderer::{
             gles::GlesRenderer,
-            ImportMem,
-            multigpu::{gbm::GbmGlesBackend, GpuManager},
+            utils::{on_commit_buffer_handler, RendererSurfaceStateUserData},
+            ImportAll, ImportMem,
+            multigpu::{gbm::GbmGlesBackend, GpuManager, MultiRenderer},
         },
         session::{
             libseat::{self, LibSeatSession},
@@ -47,34 +57,103 @@ use smithay::{
         },
         udev::{all_gpus, primary_gpu, UdevBackend, UdevEvent},
     },
-    delegate_compositor, delegate_data_device, delegate_output, delegate_seat, delegate_shm,
-    delegate_xdg_shell,
-    desktop::Space,
-    input::{SeatHandler, SeatState, Seat},
+    delegate_compositor, delegate_cursor_shape, delegate_data_control, delegate_data_device,
+    delegate_ext_data_control, delegate_fractional_scale, delegate_layer_shell, delegate_output,
+    delegate_pointer_constraints, delegate_pointer_gestures, delegate_presentation,
+    delegate_primary_selection, delegate_relative_pointer, delegate_seat, delegate_tablet_manager,
+    delegate_shm, delegate_viewporter, delegate_xdg_shell, delegate_xwayland_shell,
+    desktop::{
+        space::{space_render_elements, SpaceRenderElements},
+        utils::{
+            send_frames_surface_tree, surface_presentation_feedback_flags_from_states,
+            surface_primary_scanout_output,
+            update_surface_primary_scanout_output, OutputPresentationFeedback,
+        },
+        layer_map_for_output, LayerSurface, Space, Window, WindowSurfaceType,
+    },
+    input::{
+        keyboard::{KeyboardTarget, KeysymHandle, Layout, ModifiersState, XkbConfig},
+        pointer::{
+            AxisFrame, ButtonEvent, CursorIcon, CursorImageStatus, Focus, GestureHoldBeginEvent,
+            GestureHoldEndEvent, GesturePinchBeginEvent, GesturePinchEndEvent, GesturePinchUpdateEvent,
+            GestureSwipeBeginEvent, GestureSwipeEndEvent, GestureSwipeUpdateEvent, GrabStartData,
+            MotionEvent, PointerGrab, PointerHandle, PointerInnerHandle, RelativeMotionEvent,
+        },
+        SeatHandler, SeatState, Seat,
+    },
     output::{Mode as WlMode, Output, PhysicalProperties},
     reexports::{
-        calloop::{EventLoop, LoopHandle, RegistrationToken},
-        input::{DeviceCapability, Libinput},
+        calloop::{
+            generic::Generic,
+            signals::{Signal, Signals},
+            timer::{TimeoutAction, Timer},
+            EventLoop, Interest, LoopHandle, Mode as CalloopMode, PostAction, RegistrationToken,
+        },
+        input::{Device as InputDevice, DeviceCapability, Libinput},
         rustix::fs::OFlags,
+        wayland_protocols::{
+            wp::presentation_time::server::wp_presentation_feedback,
+            xdg::shell::server::xdg_toplevel,
+        },
         wayland_server::{
             backend::{ClientData, ClientId, DisconnectReason},
-            protocol::{wl_seat::WlSeat, wl_surface::WlSurface},
-            Display, DisplayHandle,
+            protocol::{wl_data_source::WlDataSource, wl_output::WlOutput, wl_seat::WlSeat, wl_surface::WlSurface},
+            Client, Display, DisplayHandle, Resource,
         },
     },
-    utils::{Clock, DeviceFd, Monotonic},
+    utils::{Clock, DeviceFd, IsAlive, Monotonic, Serial, Size},
     wayland::{
         buffer::BufferHandler,
-        compositor::{CompositorClientState, CompositorHandler, CompositorState},
+        compositor::{
+            get_parent, is_sync_subsurface, send_surface_state, with_states, with_surface_tree_downward,
+            CompositorClientState, CompositorHandler, CompositorState, RectangleKind, RegionAttributes,
+            TraversalAction,
+        },
+        cursor_shape::CursorShapeManagerState,
+        fractional_scale::{with_fractional_scale, FractionalScaleHandler, FractionalScaleManagerState},
         output::{OutputHandler, OutputManagerState},
+        pointer_gestures::PointerGesturesState,
+        pointer_constraints::{
+            with_pointer_constraint, PointerConstraint, PointerConstraintsHandler, PointerConstraintsState,
+        },
+        presentation::{PresentationState, Refresh},
+        relative_pointer::RelativePointerManagerState,
+        seat::WaylandFocus,
         selection::{
             data_device::{
+                clear_data_device_selection, current_data_device_selection_userdata,
+                request_data_device_client_selection, set_data_device_focus, set_data_device_selection,
                 ClientDndGrabHandler, DataDeviceHandler, ServerDndGrabHandler, DataDeviceState,
             },
-            SelectionHandler,
+            ext_data_control::{
+                DataControlHandler as ExtDataControlHandler, DataControlState as ExtDataControlState,
+            },
+            primary_selection::{
+                clear_primary_selection, current_primary_selection_userdata, request_primary_client_selection,
+                set_primary_focus, set_primary_selection, PrimarySelectionHandler, PrimarySelectionState,
+            },
+            wlr_data_control::{DataControlHandler, DataControlState},
+            SelectionHandler, SelectionSource, SelectionTarget,
+        },
+        shell::{
+            wlr_layer::{
+                Layer, LayerSurface as WlrLayerSurface, LayerSurfaceData, WlrLayerShellHandler,
+                WlrLayerShellState,
+            },
+            xdg::{
+                PopupSurface, PositionerState, SurfaceCachedState, ToplevelSurface, XdgShellHandler,
+                XdgShellState, XdgToplevelSurfaceData,
+            },
         },
-        shell::xdg::{XdgShellHandler, XdgShellState, PopupSurface, PositionerState, ToplevelSurface},
         shm::{ShmHandler, ShmState},
+        socket::ListeningSocketSource,
+        tablet_manager::{TabletDescriptor, TabletManagerState, TabletSeatHandler, TabletSeatTrait},
+        viewporter::ViewporterState,
+        xwayland_shell::{XWaylandShellHandler, XWaylandShellState},
+    },
+    xwayland::{
+        xwm::{Reorder, ResizeEdge as X11ResizeEdge, XwmId},
+        X11Surface, X11Wm, XwmHandler,
     },
 };
 use smithay_drm_extras::drm_scanner::{DrmScanEvent, DrmScanner};
@@ -85,118 +164,199 @@ use smithay::backend::renderer::{
     },
     Texture,
 };
-use smithay::utils::{Logical, Point, Scale, Physical, Transform};
+use smithay::utils::{Logical, Point, Rectangle, Scale, Physical, Transform};
 use tracing::{debug, error, info, trace, warn};
 
-/// Convert HSV hue (0-360) to RGB (0.0-1.0) with full saturation and value
-fn hue_to_rgb(hue: f32) -> (f32, f32, f32) {
-    let h = hue / 60.0;
-    let c = 1.0;  // Full saturation and value
-    let x = 1.0 - (h % 2.0 - 1.0).abs();
-    
-    let (r, g, b) = match h as i32 {
-        0 => (c, x, 0.0),
-        1 => (x, c, 0.0),
-        2 => (0.0, c, x),
-        3 => (0.0, x, c),
-        4 => (x, 0.0, c),
-        _ => (c, 0.0, x),
-    };
-    
-    (r, g, b)
-}
+use crate::clipboard::{self, Clipboard, ClipboardHandler, OfferedSelection};
+use crate::config::{Config, KeyBinding};
+use crate::edid::{EdidInfo, OutputIdentity};
+use crate::gestures::{CompositorSwipe, GestureAction};
+use crate::input::{self, DndIcon, FocusTarget};
+use crate::ipc::{self, IpcHandler, IpcServer};
+use crate::libinput_config;
+use crate::profiles::{LayoutProfile, LayoutProfiles, OutputProfile};
+use crate::xwayland::{self, X11Sockets};
+use crate::output_management::{
+    delegate_output_management, HeadConfiguration, HeadMode, ModeSetting, OutputConfiguration,
+    OutputHeadState, OutputManagementHandler, OutputManagementState,
+};
+
+/// Background behind all windows
+const CLEAR_COLOR: [f32; 4] = [0.1, 0.1, 0.3, 1.0];
 
-// Simple render element type for our compositor
-// For now we only support memory buffer rendering (for simple shapes/colors)
+/// Renderer used for all DRM outputs (GLES on the GPU that owns the output)
+type NuthatchMultiRenderer<'a, 'b> = MultiRenderer<
+    'a,
+    'b,
+    GbmGlesBackend<GlesRenderer, DrmDeviceFd>,
+    GbmGlesBackend<GlesRenderer, DrmDeviceFd>,
+>;
+
+// Render element type for our compositor: memory buffers (cursor) and client windows.
+// The macro only bounds `R: Renderer` on the enum itself, so the space's
+// element type has to be a parameter; `NuthatchRenderElements` fills it in.
 smithay::backend::renderer::element::render_elements! {
-    pub NuthatchRenderElements<R> where R: ImportMem;
+    pub NuthatchElements<R, E> where R: ImportAll + ImportMem;
     Memory=MemoryRenderBufferRenderElement<R>,
+    Surface=WaylandSurfaceRenderElement<R>,
+    Space=SpaceRenderElements<R, E>,
 }
 
-// Implement Debug for NuthatchRenderElements
-impl<R: smithay::backend::renderer::Renderer> std::fmt::Debug for NuthatchRenderElements<R> {
+pub type NuthatchRenderElements<R> = NuthatchElements<R, WaylandSurfaceRenderElement<R>>;
+
+// Implement Debug for NuthatchElements
+impl<R, E> std::fmt::Debug for NuthatchElements<R, E>
+where
+    R: smithay::backend::renderer::Renderer,
+    E: smithay::backend::renderer::element::RenderElement<R>,
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Memory(arg0) => f.debug_tuple("Memory").field(arg0).finish(),
+            Self::Surface(arg0) => f.debug_tuple("Surface").field(arg0).finish(),
+            Self::Space(_) => f.debug_tuple("Space").finish(),
             Self::_GenericCatcher(arg0) => f.debug_tuple("_GenericCatcher").field(arg0).finish(),
         }
     }
 }
 
-/// Pointer/cursor element for rendering the mouse cursor
+/// Pointer/cursor element for rendering the mouse cursor: our themed image,
+/// a surface provided by the client, or nothing if the client hid it
 pub struct PointerElement {
     buffer: Option<MemoryRenderBuffer>,
+    hotspot: Point<f64, Logical>,  // Of the themed image in `buffer`
+    status: CursorImageStatus,
 }
 
 impl Default for PointerElement {
     fn default() -> Self {
         Self {
             buffer: None,
+            hotspot: Point::from((0.0, 0.0)),
+            status: CursorImageStatus::default_named(),
         }
     }
 }
 
 impl PointerElement {
-    pub fn set_buffer(&mut self, buffer: MemoryRenderBuffer) {
+    pub fn set_buffer(&mut self, buffer: MemoryRenderBuffer, hotspot: Point<f64, Logical>) {
         self.buffer = Some(buffer);
+        self.hotspot = hotspot;
+    }
+
+    pub fn set_status(&mut self, status: CursorImageStatus) {
+        self.status = status;
     }
 }
 
 impl<T, R> AsRenderElements<R> for PointerElement
 where
     T: Texture + Clone + Send + 'static,
-    R: smithay::backend::renderer::Renderer<TextureId = T> + ImportMem,
+    R: smithay::backend::renderer::Renderer<TextureId = T> + ImportAll + ImportMem,
 {
-    type RenderElement = MemoryRenderBufferRenderElement<R>;
+    type RenderElement = NuthatchRenderElements<R>;
     
     fn render_elements<E>(
         &self,
         renderer: &mut R,
         location: Point<i32, Physical>,
         scale: Scale<f64>,
-        _alpha: f32,
+        alpha: f32,
     ) -> Vec<E>
     where
-        E: From<MemoryRenderBufferRenderElement<R>>,
+        E: From<NuthatchRenderElements<R>>,
     {
-        if let Some(buffer) = &self.buffer {
-            vec![MemoryRenderBufferRenderElement::from_buffer(
-                renderer,
-                location.to_f64(),
-                buffer,
-                None,
-                None,
-                None,
-                Kind::Cursor,
-            )
-            .expect("Failed to create cursor render element")
-            .into()]
-        } else {
-            vec![]
+        match &self.status {
+            CursorImageStatus::Hidden => vec![],
+            CursorImageStatus::Named(_) => match &self.buffer {
+                Some(buffer) => vec![NuthatchRenderElements::from(
+                    MemoryRenderBufferRenderElement::from_buffer(
+                        renderer,
+                        (location - self.hotspot.to_physical_precise_round(scale)).to_f64(),
+                        buffer,
+                        None,
+                        None,
+                        None,
+                        Kind::Cursor,
+                    )
+                    .expect("Failed to create cursor render element"),
+                )
+                .into()],
+                None => vec![],
+            },
+            CursorImageStatus::Surface(surface) => {
+                // `location` is the pointer position; the client's hotspot goes there
+                let hotspot = crate::cursor::surface_hotspot(surface).to_physical_precise_round(scale);
+                let elements: Vec<WaylandSurfaceRenderElement<R>> = render_elements_from_surface_tree(
+                    renderer,
+                    surface,
+                    location - hotspot,
+                    scale,
+                    alpha,
+                    Kind::Cursor,
+                );
+                elements
+                    .into_iter()
+                    .map(|element| NuthatchRenderElements::from(element).into())
+                    .collect()
+            }
         }
     }
 }
 
+/// Uploaded themed cursor images and their hotspots, by (shape, animation
+/// frame, scale)
+type CursorBuffers = HashMap<(CursorIcon, usize, u32), (MemoryRenderBuffer, Point<f64, Logical>)>;
+
 // Simplified state for DRM backend (without the full NuthatchState complexity)
 pub struct DrmCompositorState {
     pub start_time: std::time::Instant,
-    pub space: Space<smithay::desktop::Window>,
+    pub space: Space<Window>,
     pub clock: Clock<Monotonic>,
     pub display_handle: DisplayHandle,
     pub compositor_state: CompositorState,
     pub xdg_shell_state: XdgShellState,
+    pub layer_shell_state: WlrLayerShellState,  // Desktop shell bars, dock and wallpaper
     pub shm_state: ShmState,
     pub output_manager_state: OutputManagerState,
     pub seat_state: SeatState<DrmCompositorState>,
     pub seat: Seat<DrmCompositorState>,  // Store the seat for easy access
     pub data_device_state: DataDeviceState,
+    pub primary_selection_state: PrimarySelectionState,
+    pub data_control_state: DataControlState,
+    pub ext_data_control_state: ExtDataControlState,
+    pub clipboard: Clipboard<DrmCompositorState>,  // Saved copies, kept after their source exits
+    pub dnd_icon: Option<DndIcon>,  // Icon of the drag-and-drop in progress
+    pub fractional_scale_manager_state: FractionalScaleManagerState,
+    pub viewporter_state: ViewporterState,
+    pub presentation_state: PresentationState,
+    pub relative_pointer_manager_state: RelativePointerManagerState,
+    pub pointer_constraints_state: PointerConstraintsState,
+    pub pointer_gestures_state: PointerGesturesState,
+    pub tablet_manager_state: TabletManagerState,
+    pub cursor_shape_manager_state: CursorShapeManagerState,
+    pub output_management_state: OutputManagementState,
+    pub layout_profiles: LayoutProfiles,
+    pub config: Config,
     pub udev_data: UdevData,
-    pub frame_count: u64,  // Frame counter for animation
+    pub frame_count: u64,  // Frames rendered so far (for logging)
     pub running: bool,  // Track if compositor should keep running
     pub pointer_location: Point<f64, Logical>,  // Current cursor position
+    pub compositor_swipe: Option<CompositorSwipe>,  // Touchpad swipe bound to a compositor action
+    pub tablet_tools: HashMap<TabletToolDescriptor, TabletToolCursor>,  // Tools in proximity
+    pub input_devices: Vec<InputDevice>,  // Connected libinput devices, for re-applying config
+    pub layout_switch_binding: Option<KeyBinding>,  // Key combination cycling keyboard layouts
+    pub keyboard_layout: Option<usize>,  // Last layout announced over IPC
+    pub ipc_server: Option<IpcServer<DrmCompositorState>>,
     pub cursor: crate::cursor::Cursor,  // Cursor theme and images
     pub pointer_element: PointerElement,  // Cursor rendering element
-    pub pointer_image: Option<MemoryRenderBuffer>,  // Cached cursor image
+    pub cursor_buffers: CursorBuffers,  // Themed cursor frames per scale
+    pub animated_cursor_outputs: HashSet<String>,  // Outputs with a cursor animation frame pending
+    pub xwayland_shell_state: XWaylandShellState,
+    pub xwayland_sockets: Option<X11Sockets>,  // Our X11 display; Xwayland starts on its first client
+    pub xwm: Option<X11Wm>,  // X11 window manager, once Xwayland is ready
+    pub xwayland_client: Option<Client>,  // Xwayland's own Wayland connection, once started
+    pub xwayland_scale: i32,  // X11 coordinates per logical unit (see `update_xwayland_scale`)
 }
 
 // Supported color formats - prefer 10-bit, fall back to 8-bit
@@ -212,7 +372,7 @@ struct BackendData {
     drm_output_manager: DrmOutputManager<
         GbmAllocator<DrmDeviceFd>,
         GbmFramebufferExporter<DrmDeviceFd>,
-        (),  // Simplified - no presentation feedback for now
+        Option<OutputPresentationFeedback>,
         DrmDeviceFd,
     >,
     gbm: GbmDevice<DrmDeviceFd>,
@@ -222,18 +382,41 @@ struct BackendData {
     surfaces: HashMap<u32, SurfaceData>, // crtc handle -> surface
 }
 
+/// The crtc a `surfaces` key stands for
+fn crtc_handle(raw: u32) -> crtc::Handle {
+    drm::control::from_u32(raw).expect("crtc handles are never zero")
+}
+
 /// Data for a single display output
 struct SurfaceData {
     output: Output,
     drm_output: Option<DrmOutput<
         GbmAllocator<DrmDeviceFd>,
         GbmFramebufferExporter<DrmDeviceFd>,
-        (),  // Simplified - no presentation feedback
+        Option<OutputPresentationFeedback>,
         DrmDeviceFd,
     >>,
     render_node: DrmNode,
     connector: connector::Handle,
     mode: drm::control::Mode,
+    modes: Vec<drm::control::Mode>,  // All modes the connector supports
+    enabled: bool,  // Disabled outputs keep their CRTC but are not rendered
+    frame_pending: bool,  // A frame is queued, waiting for its (estimated) VBlank
+    redraw_needed: bool,  // Damage arrived since the last render
+    render_scheduled: bool,  // An idle render callback is already queued
+    cursor_on_plane: Option<bool>,  // Whether the pointer was last scanned out from the cursor plane
+}
+
+/// Keys the compositor handles itself instead of forwarding
+enum KeyAction {
+    Quit,
+    SwitchLayout,
+}
+
+/// Cursor of a tablet tool (pen, eraser, ...) while it's in proximity
+pub struct TabletToolCursor {
+    location: Point<f64, Logical>,
+    image: CursorImageStatus,  // Set per tool by the client under it
 }
 
 /// Main DRM backend state
@@ -259,24 +442,44 @@ impl DrmCompositorState {
         // Initialize Wayland protocols
         let compositor_state = CompositorState::new::<Self>(&dh);
         let xdg_shell_state = XdgShellState::new::<Self>(&dh);
+        let layer_shell_state = WlrLayerShellState::new::<Self>(&dh);
         let shm_state = ShmState::new::<Self>(&dh, vec![]);
         let output_manager_state = OutputManagerState::new_with_xdg_output::<Self>(&dh);
         let mut seat_state = SeatState::new();
         let data_device_state = DataDeviceState::new::<Self>(&dh);
+        let primary_selection_state = PrimarySelectionState::new::<Self>(&dh);
+        // Clipboard managers and wl-copy/wl-paste; every client may use them
+        let data_control_state = DataControlState::new::<Self, _>(&dh, Some(&primary_selection_state), |_| true);
+        let ext_data_control_state =
+            ExtDataControlState::new::<Self, _>(&dh, Some(&primary_selection_state), |_| true);
+        let fractional_scale_manager_state = FractionalScaleManagerState::new::<Self>(&dh);
+        let viewporter_state = ViewporterState::new::<Self>(&dh);
+        let presentation_state = PresentationState::new::<Self>(&dh, clock.id() as u32);
+        let relative_pointer_manager_state = RelativePointerManagerState::new::<Self>(&dh);
+        let pointer_constraints_state = PointerConstraintsState::new::<Self>(&dh);
+        let pointer_gestures_state = PointerGesturesState::new::<Self>(&dh);
+        let tablet_manager_state = TabletManagerState::new::<Self>(&dh);
+        let cursor_shape_manager_state = CursorShapeManagerState::new::<Self>(&dh);
+        let output_management_state = OutputManagementState::new::<Self>(&dh);
+        let xwayland_shell_state = XWaylandShellState::new::<Self>(&dh);
 
         // Add a seat for input
         let mut seat = seat_state.new_wl_seat(&dh, "seat-0");
         seat.add_keyboard(Default::default(), 200, 25).unwrap();
         seat.add_pointer();
+        seat.add_touch();
 
         // Load cursor theme
         info!("Loading cursor theme...");
         let cursor = crate::cursor::Cursor::load();
         info!("✅ Cursor theme loaded");
 
-        // Initialize pointer at screen center (assuming 1920x1200 for now)
-        let pointer_location = Point::from((960.0, 600.0));
-        info!("🖱️  Initial pointer location: ({}, {})", pointer_location.x, pointer_location.y);
+        let config = Config::load();
+        let clipboard = Clipboard::new(udev_data.loop_handle.clone(), dh.clone(), &config.clipboard);
+
+        // The pointer is moved to the centre of the primary output once the
+        // first outputs are discovered (see `device_changed`)
+        let pointer_location = Point::from((0.0, 0.0));
 
         Self {
             start_time,
@@ -285,18 +488,47 @@ impl DrmCompositorState {
             display_handle: dh,
             compositor_state,
             xdg_shell_state,
+            layer_shell_state,
             shm_state,
             output_manager_state,
             seat_state,
             seat,  // Store the seat for input handling
             data_device_state,
+            primary_selection_state,
+            data_control_state,
+            ext_data_control_state,
+            clipboard,
+            dnd_icon: None,
+            fractional_scale_manager_state,
+            viewporter_state,
+            presentation_state,
+            relative_pointer_manager_state,
+            pointer_constraints_state,
+            pointer_gestures_state,
+            tablet_manager_state,
+            cursor_shape_manager_state,
+            output_management_state,
+            layout_profiles: LayoutProfiles::load(),
+            config,
             udev_data,
             frame_count: 0,
             running: true,
             pointer_location,
+            compositor_swipe: None,
+            tablet_tools: HashMap::new(),
+            input_devices: Vec::new(),
+            layout_switch_binding: None,
+            keyboard_layout: None,
+            ipc_server: None,
             cursor,
             pointer_element: PointerElement::default(),
-            pointer_image: None,
+            cursor_buffers: HashMap::new(),
+            animated_cursor_outputs: HashSet::new(),
+            xwayland_shell_state,
+            xwayland_sockets: None,
+            xwm: None,
+            xwayland_client: None,
+            xwayland_scale: 1,
         }
     }
 }
@@ -320,7 +552,10 @@ impl UdevData {
 }
 
 /// Initialize and run the DRM backend
-pub fn run_udev() -> Result<()> {
+///
+/// Runs until Ctrl+Alt+Q / Ctrl+Alt+Backspace, SIGINT or SIGTERM. With
+/// `test_timeout` set, also exits after that long (safety net for VM/TTY testing).
+pub fn run_udev(test_timeout: Option<Duration>) -> Result<()> {
     info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     info!("🚀 STARTING FULL DRM BACKEND");
     info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
@@ -334,9 +569,8 @@ pub fn run_udev() -> Result<()> {
     
     // Create Wayland display
     info!("Step 2: Creating Wayland display...");
-    let display = Display::new()
+    let display: Display<DrmCompositorState> = Display::new()
         .context("Failed to create Wayland display")?;
-    let display_handle = display.handle();
     info!("✅ Wayland display created");
     
     // Initialize session for VT switching and device access
@@ -385,6 +619,7 @@ pub fn run_udev() -> Result<()> {
     
     // Initialize compositor state
     let mut state = DrmCompositorState::new(&display, &event_loop, udev_data);
+    apply_keyboard_config(&mut state);
     
     // Initialize udev backend for device discovery
     info!("Initializing udev backend...");
@@ -424,16 +659,18 @@ pub fn run_udev() -> Result<()> {
                     let time = InputEventTrait::time_msec(&event);
                     let serial = SERIAL_COUNTER.next_serial();
                     
+                    let layout_switch = state.layout_switch_binding;
+
                     // Use keyboard.input() to properly update modifier state
                     if let Some(keyboard) = state.seat.get_keyboard() {
-                        keyboard.input(
+                        let action = keyboard.input(
                             state,
                             keycode,
                             key_state,
                             serial,
                             time,
                             |_state, modifiers, handle| {
-                                // Check for exit shortcuts on key press
+                                // Check for compositor shortcuts on key press
                                 if key_state == KeyState::Pressed {
                                     let raw_code = keycode.raw();
                                     let is_q = raw_code == 24;  // Q key
@@ -442,9 +679,13 @@ pub fn run_udev() -> Result<()> {
                                     if modifiers.ctrl && modifiers.alt && (is_q || is_backspace) {
                                         info!("🛑 Exit key combination detected (Ctrl+Alt+{}) - shutting down gracefully",
                                               if is_q { "Q" } else { "Backspace" });
-                                        // Signal exit - but we can't set state.running here due to borrow
-                                        // So we'll return Intercept to signal we handled it
-                                        return FilterResult::Intercept(true);
+                                        return FilterResult::Intercept(KeyAction::Quit);
+                                    }
+
+                                    if layout_switch.is_some_and(|binding| {
+                                        binding.matches(modifiers, handle.raw_latin_sym_or_raw_current_sym())
+                                    }) {
+                                        return FilterResult::Intercept(KeyAction::SwitchLayout);
                                     }
                                     
                                     // Log key presses with modifier state for debugging
@@ -460,42 +701,93 @@ pub fn run_udev() -> Result<()> {
                                 FilterResult::Forward  // Forward other keys normally
                             }
                         );
-                        
-                        // Check if we should exit (ugly workaround for borrow checker)
-                        // The FilterResult::Intercept(true) signals we should exit
-                        if key_state == KeyState::Pressed {
-                            if let Some(kbd) = state.seat.get_keyboard() {
-                                let mods = kbd.modifier_state();
-                                let raw_code = keycode.raw();
-                                let is_q = raw_code == 24;
-                                let is_backspace = raw_code == 22;
-                                if mods.ctrl && mods.alt && (is_q || is_backspace) {
-                                    state.running = false;
-                                }
-                            }
+
+                        match action {
+                            Some(KeyAction::Quit) => state.running = false,
+                            Some(KeyAction::SwitchLayout) => switch_keyboard_layout(state, None),
+                            None => {}
                         }
+                        // XKB options like grp:alt_shift_toggle switch layouts by themselves
+                        check_keyboard_layout(state);
                     }
                 }
-                InputEvent::DeviceAdded { device } => {
+                InputEvent::DeviceAdded { mut device } => {
                     info!("🔌 Input device added: {:?}", device.name());
+                    libinput_config::apply(&mut device, &state.config.input);
+                    state.input_devices.push(device.clone());
+                    if device.has_capability(DeviceCapability::TabletTool) {
+                        state
+                            .seat
+                            .tablet_seat()
+                            .add_tablet::<DrmCompositorState>(&state.display_handle, &TabletDescriptor::from(&device));
+                    }
                 }
                 InputEvent::DeviceRemoved { device } => {
                     info!("🔌 Input device removed: {:?}", device.name());
+                    state.input_devices.retain(|known| *known != device);
+                    if device.has_capability(DeviceCapability::TabletTool) {
+                        let tablet_seat = state.seat.tablet_seat();
+                        tablet_seat.remove_tablet(&TabletDescriptor::from(&device));
+                        // Tools aren't tied to a tablet; drop them with the last one
+                        if tablet_seat.count_tablets() == 0 {
+                            tablet_seat.clear_tools();
+                            state.tablet_tools.clear();
+                        }
+                    }
                 }
                 InputEvent::PointerMotion { event } => {
-                    use smithay::backend::input::{PointerMotionEvent};
-                    let delta = event.delta();
-                    state.pointer_location += delta;
-                    // Clamp to screen bounds (assuming 1920x1200)
-                    state.pointer_location.x = state.pointer_location.x.max(0.0).min(1920.0);
-                    state.pointer_location.y = state.pointer_location.y.max(0.0).min(1200.0);
-                    info!("🖱️  Pointer moved: delta=({:.2}, {:.2}) -> pos=({:.1}, {:.1})", 
-                           delta.x, delta.y, state.pointer_location.x, state.pointer_location.y);
+                    pointer_motion_relative(state, &event);
+                }
+                InputEvent::PointerMotionAbsolute { event } => {
+                    use smithay::backend::input::AbsolutePositionEvent;
+                    // Tablets and VM pointers span the bounding box of the whole layout
+                    let Some(layout) = layout_bounding_box(&state.space) else {
+                        return;  // No outputs to point at yet
+                    };
+                    let position = layout.loc.to_f64() + event.position_transformed(layout.size);
+                    state.pointer_location = clamp_to_outputs(&state.space, position);
+                    trace!("🖱️  Pointer moved (absolute) -> pos=({:.1}, {:.1})",
+                           state.pointer_location.x, state.pointer_location.y);
+                    pointer_moved(state, InputEventTrait::time_msec(&event));
                 }
                 InputEvent::PointerButton { event } => {
                     use smithay::backend::input::PointerButtonEvent;
-                    let button = event.button_code();
-                    debug!("🖱️  Mouse button: code={}", button);
+                    debug!("🖱️  Mouse button: code={} state={:?}", event.button_code(), event.state());
+                    let seat = state.seat.clone();
+                    if input::button_moves_focus(&seat, &event) {
+                        let focus = input::click_focus(&mut state.space, &seat, state.pointer_location);
+                        input::set_keyboard_focus(state, &seat, focus);
+                        // Raising a window changes the stacking order on screen
+                        schedule_render_all(state);
+                    }
+                    input::pointer_button(state, &seat, &event);
+                }
+                InputEvent::PointerAxis { event } => {
+                    let seat = state.seat.clone();
+                    input::pointer_axis(state, &seat, &event);
+                }
+                InputEvent::GestureSwipeBegin { .. }
+                | InputEvent::GestureSwipeUpdate { .. }
+                | InputEvent::GestureSwipeEnd { .. }
+                | InputEvent::GesturePinchBegin { .. }
+                | InputEvent::GesturePinchUpdate { .. }
+                | InputEvent::GesturePinchEnd { .. }
+                | InputEvent::GestureHoldBegin { .. }
+                | InputEvent::GestureHoldEnd { .. } => {
+                    handle_gesture(state, event);
+                }
+                InputEvent::TabletToolAxis { .. }
+                | InputEvent::TabletToolProximity { .. }
+                | InputEvent::TabletToolTip { .. }
+                | InputEvent::TabletToolButton { .. } => {
+                    handle_tablet(state, event);
+                }
+                InputEvent::TouchDown { .. }
+                | InputEvent::TouchMotion { .. }
+                | InputEvent::TouchUp { .. }
+                | InputEvent::TouchFrame { .. }
+                | InputEvent::TouchCancel { .. } => {
+                    handle_touch(state, event);
                 }
                 _ => {}
             }
@@ -568,44 +860,124 @@ pub fn run_udev() -> Result<()> {
         }
     }
     
+    // Clients talk to us through the display, dispatched whenever its fd is readable
+    let display_token = loop_handle
+        .insert_source(
+            Generic::new(display, Interest::READ, CalloopMode::Level),
+            |_, display, state| {
+                // Safety: the display is only dropped by removing this source
+                unsafe {
+                    display.get_mut().dispatch_clients(state)?;
+                }
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to insert Wayland display source: {}", e))?;
+
+    // Listen for new clients on $XDG_RUNTIME_DIR/wayland-N
+    let socket = ListeningSocketSource::new_auto()
+        .context("Failed to create Wayland socket")?;
+    let socket_name = socket.socket_name().to_string_lossy().into_owned();
+    let socket_token = loop_handle
+        .insert_source(socket, |client_stream, _, state| {
+            if let Err(e) = state
+                .display_handle
+                .insert_client(client_stream, Arc::new(ClientState::default()))
+            {
+                warn!("Failed to add Wayland client: {}", e);
+            }
+        })
+        .map_err(|e| anyhow::anyhow!("Failed to insert Wayland socket source: {}", e))?;
+    // Safety: we are still single-threaded here
+    unsafe { std::env::set_var("WAYLAND_DISPLAY", &socket_name) };
+    info!("✅ Listening on Wayland socket: {}", socket_name);
+
+    // IPC for the shell (layout indicator, etc.), named after the Wayland socket
+    match IpcServer::start(loop_handle.clone(), &socket_name) {
+        Ok(server) => state.ipc_server = Some(server),
+        Err(e) => warn!("IPC unavailable: {}", e),
+    }
+
+    start_xwayland(&mut state);
+
+    // SIGINT (Ctrl+C over SSH) and SIGTERM (systemd, pkill) stop the loop cleanly
+    // SIGHUP reloads the config file
+    let signals = Signals::new(&[Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP])
+        .context("Failed to create signal source")?;
+    loop_handle
+        .insert_source(signals, |event, _, state| match event.signal() {
+            Signal::SIGHUP => reload_config(state),
+            signal => {
+                info!("🛑 Received {:?} - shutting down", signal);
+                state.running = false;
+            }
+        })
+        .map_err(|e| anyhow::anyhow!("Failed to insert signal source: {}", e))?;
+
     info!("🎉 DRM backend initialized successfully!");
-    info!("📊 Event loop status: Starting...");
     info!("Compositor is running. Press Ctrl+Alt+Q or Ctrl+Alt+Backspace to exit.");
-    info!("⚠️  SAFETY: Will auto-exit after 10 seconds for testing");
+    if let Some(limit) = test_timeout {
+        info!("⚠️  Test timeout: will exit after {:?}", limit);
+    }
     
-    // Main event loop - run until user quits or timeout
+    // Main event loop - run until the user quits, a signal arrives or the test timeout hits
     info!("🔄 Entering main event loop...");
     let start_time = std::time::Instant::now();
-    let mut iteration = 0u64;
-    loop {
-        // Check if user requested exit via keyboard shortcut
-        if !state.running {
-            info!("👋 User requested exit - shutting down gracefully");
-            break;
-        }
-        
-        // SAFETY: Auto-exit after 10 seconds to prevent hangs during testing
-        if start_time.elapsed() > Duration::from_secs(10) {
-            info!("⏱️  10 second timeout reached - exiting for safety");
+    let mut result = Ok(());
+    while state.running {
+        // Without a test timeout we sleep until there is something to do
+        let timeout = match test_timeout {
+            Some(limit) if start_time.elapsed() >= limit => {
+                info!("⏱️  Test timeout of {:?} reached - exiting", limit);
+                break;
+            }
+            Some(limit) => Some(limit - start_time.elapsed()),
+            None => None,
+        };
+
+        if let Err(e) = event_loop.dispatch(timeout, &mut state) {
+            error!("❌ Event loop error: {:?}", e);
+            result = Err(e).context("Event loop error");
             break;
         }
-        
-        iteration += 1;
-        if iteration % 60 == 0 {  // Log every ~1 second at 60fps
-            info!("Event loop iteration: {} ({}s elapsed)", iteration, start_time.elapsed().as_secs());
-        }
-        
-        match event_loop.dispatch(Some(Duration::from_millis(16)), &mut state) {
-            Ok(_) => {},
-            Err(e) => {
-                error!("❌ Event loop error: {:?}", e);
-                return Err(e).context("Event loop error");
-            }
+
+        if let Err(e) = state.display_handle.flush_clients() {
+            warn!("Failed to flush clients: {}", e);
         }
     }
     
     info!("🛑 Exiting compositor safely...");
-    Ok(())
+    shutdown(&mut state, socket_token, display_token);
+    result
+}
+
+/// Tear everything down in an order that leaves the system usable:
+/// stop accepting clients, disconnect the existing ones, then give the
+/// displays back while we still hold the session.
+fn shutdown(state: &mut DrmCompositorState, socket_token: RegistrationToken, display_token: RegistrationToken) {
+    let loop_handle = state.udev_data.loop_handle.clone();
+
+    // Dropping the listening socket unlinks it and its lock file
+    loop_handle.remove(socket_token);
+    info!("✅ Wayland socket removed");
+
+    if let Some(ipc_server) = state.ipc_server.as_mut() {
+        ipc_server.shutdown();
+        info!("✅ IPC socket removed");
+    }
+
+    // Dropping the display closes every client connection
+    loop_handle.remove(display_token);
+    info!("✅ Clients disconnected");
+
+    // Dropping each DrmDevice restores the CRTC state we found at startup,
+    // so the console (or the previous session) comes back
+    for (node, backend) in state.udev_data.backends.drain() {
+        loop_handle.remove(backend.registration_token);
+        state.udev_data.gpus.as_mut().remove_node(&backend.render_node);
+        drop(backend);
+        info!("✅ Released DRM device {}", node);
+    }
 }
 
 /// Handle device changes (connector hotplug, etc.)
@@ -623,6 +995,8 @@ fn device_changed(state: &mut DrmCompositorState, node: DrmNode) {
         return;
     };
 
+    let had_outputs = state.space.outputs().next().is_some();
+
     // Scan for connector changes
     info!("Scanning connectors...");
     let scan_result = match device.drm_scanner.scan_connectors(device.drm_output_manager.device()) {
@@ -671,6 +1045,21 @@ fn device_changed(state: &mut DrmCompositorState, node: DrmNode) {
             }
         }
     }
+
+    // The set of monitors may have changed (dock/undock), bring back its layout
+    restore_layout_profile(state);
+    arrange_layers(state);
+    update_xwayland_scale(state);
+
+    if !had_outputs {
+        // First outputs appeared: start the pointer in the middle of the primary one
+        if let Some(geometry) = primary_output(&state.space).and_then(|o| state.space.output_geometry(&o)) {
+            state.pointer_location = geometry.loc.to_f64() + geometry.size.to_f64().downscale(2.0).to_point();
+            info!("🖱️  Initial pointer location: ({}, {})", state.pointer_location.x, state.pointer_location.y);
+        }
+    } else {
+        clamp_pointer_to_outputs(state);
+    }
 }
 
 /// Handle connector connection
@@ -723,6 +1112,13 @@ fn connector_connected(
     // Get physical size
     let (phys_w, phys_h) = connector.size().unwrap_or((0, 0));
     info!("Physical size: {}x{} mm", phys_w, phys_h);
+
+    // Read make/model/serial from the EDID
+    let edid = EdidInfo::for_connector(device.drm_output_manager.device(), connector.handle());
+    match &edid {
+        Some(edid) => info!("EDID: make={:?} model={:?} serial={:?}", edid.make, edid.model, edid.serial),
+        None => warn!("No EDID available for {}", output_name),
+    }
     
     // Create Wayland Output
     info!("Creating Wayland Output...");
@@ -731,11 +1127,16 @@ fn connector_connected(
         PhysicalProperties {
             size: (phys_w as i32, phys_h as i32).into(),
             subpixel: connector.subpixel().into(),
-            make: "Unknown".into(),
-            model: "Unknown".into(),
+            make: edid.as_ref().map_or_else(|| "Unknown".into(), |e| e.make.clone()),
+            model: edid.as_ref().map_or_else(|| "Unknown".into(), |e| e.model.clone()),
         },
     );
-    info!("✅ Created Wayland Output");
+    if let Some(edid) = edid {
+        output.user_data().insert_if_missing(|| OutputIdentity(edid.identifier()));
+        // smithay's physical properties have no serial; output management reads it from here
+        output.user_data().insert_if_missing(|| edid);
+    }
+    info!("✅ Created Wayland Output (identity: {})", OutputIdentity::of(&output));
     
     // Create global for clients
     info!("Creating global for clients...");
@@ -750,10 +1151,31 @@ fn connector_connected(
     let position = (x, 0).into();
     info!("Output position: {:?}", position);
     
+    // Pick a scale: explicit config override, else guessed from the DPI.
+    // A saved layout profile (restored after the scan) takes precedence over both.
+    let scale = state
+        .config
+        .outputs
+        .scale_for(&OutputIdentity::of(&output), &output_name)
+        .or_else(|| {
+            state
+                .config
+                .outputs
+                .auto_scale
+                .then(|| default_scale((phys_w, phys_h), wl_mode.size))
+        })
+        .unwrap_or(1.0);
+    info!("Output scale: {}", scale);
+
     // Configure output
     info!("Configuring output state...");
     output.set_preferred(wl_mode);
-    output.change_current_state(Some(wl_mode), None, None, Some(position));
+    output.change_current_state(
+        Some(wl_mode),
+        None,
+        Some(smithay::output::Scale::Fractional(scale)),
+        Some(position),
+    );
     state.space.map_output(&output, position);
     info!("✅ Output configured and mapped to space");
     
@@ -772,6 +1194,12 @@ fn connector_connected(
         render_node: device.render_node,
         connector: connector.handle(),
         mode: drm_mode,
+        modes: connector.modes().to_vec(),
+        enabled: true,
+        frame_pending: false,
+        redraw_needed: false,
+        render_scheduled: false,
+        cursor_on_plane: None,
     };
     
     info!("Storing surface data for CRTC {:?}...", crtc);
@@ -781,6 +1209,8 @@ fn connector_connected(
     info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     info!("✅ CONNECTOR_CONNECTED COMPLETE: {}", output_name);
     info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+
+    refresh_output_management(state);
     
     // Trigger initial render to start VBlank cycle
     info!("🎬 Triggering initial render to start VBlank events...");
@@ -789,7 +1219,7 @@ fn connector_connected(
 
 /// Handle connector disconnection
 fn connector_disconnected(
-    _state: &mut DrmCompositorState,
+    state: &mut DrmCompositorState,
     node: DrmNode,
     connector: connector::Info,
     crtc: crtc::Handle,
@@ -800,178 +1230,2301 @@ fn connector_disconnected(
         connector.interface_id(),
         crtc
     );
-    
-    // TODO: Clean up surface, remove output
+
+    let Some(device) = state.udev_data.backends.get_mut(&node) else {
+        warn!("Device {} not found in backends", node);
+        return;
+    };
+
+    // Dropping the surface drops its DrmOutput, releasing the CRTC
+    if let Some(surface) = device.surfaces.remove(&crtc.into()) {
+        // Layer surfaces belong to one output; the shell makes new ones
+        for layer in layer_map_for_output(&surface.output).layers() {
+            layer.layer_surface().send_close();
+        }
+        state.space.unmap_output(&surface.output);
+        info!("✅ Output {} removed", surface.output.name());
+    }
+
+    clamp_pointer_to_outputs(state);
+    update_xwayland_scale(state);
+    refresh_output_management(state);
 }
 
-/// Render a frame for a specific surface
-fn render_surface(
+/// Guess a comfortable scale from a monitor's physical size and resolution.
+///
+/// Laptop panels are viewed from closer than desktop monitors, so they get a
+/// higher target DPI at 1x. The result is snapped to 0.25 steps and reduced if
+/// it would leave less than 1024x600 logical pixels.
+fn default_scale(size_mm: (u32, u32), mode_size: smithay::utils::Size<i32, Physical>) -> f64 {
+    const SCALE_STEP: f64 = 0.25;
+    const MIN_LOGICAL_SIZE: (f64, f64) = (1024.0, 600.0);
+
+    let (width_mm, height_mm) = (size_mm.0 as f64, size_mm.1 as f64);
+    // Projectors and some TVs report nothing, or their aspect ratio in cm
+    if width_mm < 100.0 || height_mm < 60.0 || mode_size.w <= 0 || mode_size.h <= 0 {
+        return 1.0;
+    }
+
+    let diagonal_inches = width_mm.hypot(height_mm) / 25.4;
+    let dpi = mode_size.w as f64 / (width_mm / 25.4);
+    let target_dpi = if diagonal_inches < 20.0 { 135.0 } else { 110.0 };
+
+    let mut scale = ((dpi / target_dpi) / SCALE_STEP).round() * SCALE_STEP;
+    scale = scale.clamp(1.0, 3.0);
+    while scale > 1.0
+        && (mode_size.w as f64 / scale < MIN_LOGICAL_SIZE.0 || mode_size.h as f64 / scale < MIN_LOGICAL_SIZE.1)
+    {
+        scale -= SCALE_STEP;
+    }
+
+    debug!(
+        "Default scale for {:.1}\" panel at {:.0} DPI: {}",
+        diagonal_inches, dpi, scale
+    );
+    scale
+}
+
+/// Convert a DRM mode into the form advertised over output management
+fn head_mode(mode: &drm::control::Mode) -> HeadMode {
+    let wl_mode = WlMode::from(*mode);
+    HeadMode {
+        size: wl_mode.size,
+        refresh: wl_mode.refresh,
+        preferred: mode.mode_type().contains(ModeTypeFlags::PREFERRED),
+    }
+}
+
+/// Pick the connector mode matching a client's mode request
+fn resolve_mode(modes: &[drm::control::Mode], setting: &ModeSetting) -> Option<drm::control::Mode> {
+    let head_modes: Vec<HeadMode> = modes.iter().map(head_mode).collect();
+    setting.resolve(&head_modes).map(|index| modes[index])
+}
+
+/// Push the current state of every connector to output management clients
+fn refresh_output_management(state: &mut DrmCompositorState) {
+    let mut heads = Vec::new();
+    for device in state.udev_data.backends.values() {
+        for surface in device.surfaces.values() {
+            let output = &surface.output;
+            let props = output.physical_properties();
+            heads.push(OutputHeadState {
+                name: output.name(),
+                description: output.description(),
+                make: props.make,
+                model: props.model,
+                serial_number: output
+                    .user_data()
+                    .get::<EdidInfo>()
+                    .map_or_else(String::new, |edid| edid.serial.clone()),
+                physical_size: (props.size.w, props.size.h),
+                modes: surface.modes.iter().map(head_mode).collect(),
+                enabled: surface.enabled,
+                current_mode: Some(head_mode(&surface.mode)),
+                position: output.current_location(),
+                transform: output.current_transform(),
+                scale: output.current_scale().fractional_scale(),
+            });
+        }
+    }
+
+    state
+        .output_management_state
+        .update::<DrmCompositorState>(heads);
+}
+
+/// Resolve a client configuration into (node, crtc, mode) per head.
+/// Returns None if any head is unknown or asks for something we can't do.
+fn resolve_output_configuration(
+    state: &DrmCompositorState,
+    config: &OutputConfiguration,
+) -> Option<Vec<(DrmNode, crtc::Handle, Option<drm::control::Mode>)>> {
+    config
+        .iter()
+        .map(|(name, head)| {
+            let (node, crtc, surface) = state
+                .udev_data
+                .backends
+                .iter()
+                .flat_map(|(node, device)| {
+                    device
+                        .surfaces
+                        .iter()
+                        .map(move |(crtc, surface)| (*node, crtc_handle(*crtc), surface))
+                })
+                .find(|(_, _, surface)| surface.output.name() == *name)
+                .or_else(|| {
+                    warn!("Output configuration references unknown head {}", name);
+                    None
+                })?;
+
+            let mode = match head {
+                HeadConfiguration::Disabled => None,
+                HeadConfiguration::Enabled { adaptive_sync: Some(true), .. } => {
+                    warn!("Adaptive sync is not supported ({})", name);
+                    return None;
+                }
+                HeadConfiguration::Enabled { mode: Some(setting), .. } => {
+                    let mode = resolve_mode(&surface.modes, setting);
+                    if mode.is_none() {
+                        warn!("Mode {:?} is not supported by {}", setting, name);
+                    }
+                    Some(mode?)
+                }
+                HeadConfiguration::Enabled { mode: None, .. } => Some(surface.mode),
+            };
+
+            Some((node, crtc, mode))
+        })
+        .collect()
+}
+
+/// Give a head's DRM output a new pending mode. The compositor checks the
+/// mode with an atomic test commit on the head's CRTC alone; the switch itself
+/// happens with the next frame. Heads without a DrmOutput (disabled, or not
+/// rendered yet) get a test commit on a scratch surface instead and pick up
+/// `surface.mode` when their first frame initializes one.
+fn set_pending_mode(
     state: &mut DrmCompositorState,
     node: DrmNode,
     crtc: crtc::Handle,
-) {
-    info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-    info!("🎬 RENDER_SURFACE called");
-    info!("   Node: {}, CRTC: {:?}", node, crtc);
-    info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-    
-    // Check if DRM output needs initialization (don't hold device borrow)
-    let needs_init = state.udev_data.backends.get(&node)
-        .and_then(|d| d.surfaces.get(&(crtc.into())))
-        .map(|s| s.drm_output.is_none())
-        .unwrap_or(false);
-    
-    if needs_init {
-        // Initialize DRM output on first render
-        info!("🎨 Initializing DRM output for first render!");
-        
-        // CRITICAL: Get render_node WITHOUT holding mutable borrows
-        let render_node = state.udev_data.backends.get(&node)
-            .and_then(|d| d.surfaces.get(&(crtc.into())))
-            .map(|s| s.render_node.clone())
-            .expect("Surface must exist");
-        
-        // Get renderer (accessing GPU manager - must not have device borrow active)
-        let mut renderer = state.udev_data.gpus.single_renderer(&render_node).unwrap();
-        
-        // NOW get mutable device reference for initialization
-        let device = state.udev_data.backends.get_mut(&node).expect("Device must exist");
-        let surface = device.surfaces.get_mut(&(crtc.into())).unwrap();
-        
-        // Create empty render elements for initialization
-        use smithay::backend::renderer::multigpu::MultiRenderer;
-        type NuthatchMultiRenderer<'a, 'b> = MultiRenderer<
-            'a,
-            'b,
-            GbmGlesBackend<GlesRenderer, DrmDeviceFd>,
-            GbmGlesBackend<GlesRenderer, DrmDeviceFd>,
-        >;
-        
-        let render_elements: DrmOutputRenderElements<NuthatchMultiRenderer, NuthatchRenderElements<NuthatchMultiRenderer>> = 
-            DrmOutputRenderElements::new();
-        
-        match device.drm_output_manager.initialize_output(
-            crtc,
-            surface.mode,
-            &[surface.connector],
-            &surface.output,
-            None,  // No plane restrictions for now
-            &mut renderer,
-            &render_elements,
-        ) {
-            Ok(drm_output) => {
-                info!("✅ DRM output initialized successfully!");
-                surface.drm_output = Some(drm_output);
-            }
-            Err(e) => {
-                error!("Failed to initialize DRM output: {}", e);
-                return;
-            }
-        }
-        // device and surface borrows dropped here
-    }
-    
-    // CRITICAL BORROW ORDERING:
-    // 1. Get render_node WITHOUT holding device mutable borrow
-    let render_node = state.udev_data.backends.get(&node)
-        .and_then(|d| d.surfaces.get(&(crtc.into())))
-        .map(|s| s.render_node.clone())
-        .expect("Surface must exist");
-    
-    info!("🎨 Getting renderer...");
-    // 2. Get renderer (needs access to state.udev_data.gpus)
-    let mut renderer = state.udev_data.gpus.single_renderer(&render_node).unwrap();
-    
-    info!("✅ Got renderer, now getting output...");
-    // 3. Get mutable device/surface references to extract drm_output
-    let mut drm_output = {
-        let device = state.udev_data.backends.get_mut(&node).expect("Device must exist");
-        let surface = device.surfaces.get_mut(&(crtc.into())).unwrap();
-        // Take ownership of drm_output to release device borrow
-        surface.drm_output.take().expect("DRM output must exist")
-    }; // device borrow dropped here
-    
-    info!("🎨 Getting renderer...");
-    let mut renderer = state.udev_data.gpus.single_renderer(&state.udev_data.primary_gpu)
-        .expect("Failed to get renderer");
-    info!("✅ Got renderer, now getting output...");
+    mode: drm::control::Mode,
+) -> bool {
+    let Some(device) = state.udev_data.backends.get_mut(&node) else {
+        return false;
+    };
+    let Some(surface) = device.surfaces.get(&crtc.into()) else {
+        return false;
+    };
 
-    info!("🎨 Rendering frame...");
-    // Animate color based on frame count to create changing content
-    // This ensures damage tracking detects changes and allows continuous VBlanks
-    state.frame_count += 1;
-    let hue = (state.frame_count as f32 * 2.0) % 360.0;  // Cycle through hues
-    let (r, g, b) = hue_to_rgb(hue);
-    let clear_color = [r, g, b, 1.0];
-    info!("   Frame #{}: hue={:.1}° color=({:.2},{:.2},{:.2})", 
-          state.frame_count, hue, r, g, b);
-    
-    // Load cursor image if not cached
-    if state.pointer_image.is_none() {
-        use smithay::backend::allocator::Fourcc;
-        // Use scale 2 for a larger, more visible cursor (48x48 instead of 24x24)
-        let cursor_image = state.cursor.get_image(2, Duration::ZERO);
-        let buffer = MemoryRenderBuffer::from_slice(
-            &cursor_image.pixels_rgba,
-            Fourcc::Argb8888,
-            (cursor_image.width as i32, cursor_image.height as i32),
-            1,
-            Transform::Normal,
-            None,
+    let result = match surface.drm_output.as_ref() {
+        Some(drm_output) => drm_output
+            .with_compositor(|compositor| compositor.use_mode(mode))
+            .map_err(|e| format!("{:?}", e)),
+        None => device
+            .drm_output_manager
+            .device_mut()
+            .create_surface(crtc, mode, &[surface.connector])
+            .and_then(|scratch| scratch.use_mode(mode))
+            .map_err(|e| e.to_string()),
+    };
+    if let Err(e) = result {
+        warn!(
+            "{} rejected mode {}x{}: {}",
+            surface.output.name(),
+            mode.size().0,
+            mode.size().1,
+            e
         );
-        state.pointer_element.set_buffer(buffer.clone());
-        state.pointer_image = Some(buffer);
-        info!("✅ Loaded cursor image ({}x{}) at scale 2", cursor_image.width, cursor_image.height);
+        return false;
     }
-    
-    // Render cursor at current pointer location
-    let scale = Scale::from(1.0);
-    let cursor_pos = state.pointer_location.to_physical(scale).to_i32_round();
-    let cursor_elements: Vec<MemoryRenderBufferRenderElement<_>> = state.pointer_element
-        .render_elements(&mut renderer, cursor_pos, scale, 1.0);
-    
-    let mut elements: Vec<NuthatchRenderElements<_>> = cursor_elements
-        .into_iter()
-        .map(NuthatchRenderElements::from)
-        .collect();
-    
-    info!("🖱️  Rendering cursor at ({}, {}) - {} elements", 
-          cursor_pos.x, cursor_pos.y, elements.len());
+    true
+}
 
-    
-    use smithay::backend::drm::compositor::FrameFlags;
-    
-    match drm_output.render_frame(&mut renderer, &elements, clear_color, FrameFlags::DEFAULT) {
-        Ok(render_result) => {
-            info!("✅ Frame rendered (is_empty: {})", render_result.is_empty);
-            
-            // Queue frame regardless of damage tracking for now
-            // This ensures we get continuous VBlanks during testing
-            match drm_output.queue_frame(()) {
-                Ok(_) => {
-                    info!("✅ Frame queued - waiting for next VBlank");
-                }
-                Err(e) => {
-                    error!("❌ Failed to queue frame for {:?}: {}", crtc, e);
-                }
-            }
+/// Test-commit the new mode of every head that changes mode or comes back
+/// on, and return the heads now pending a switch. If any head rejects its
+/// mode, the heads tested before it go back to their current mode so a
+/// failed configuration leaves every output untouched.
+fn test_output_modes(
+    state: &mut DrmCompositorState,
+    resolved: &[(DrmNode, crtc::Handle, Option<drm::control::Mode>)],
+) -> Option<Vec<(DrmNode, crtc::Handle)>> {
+    let mut switched = Vec::new();
+    for &(node, crtc, mode) in resolved {
+        let Some(mode) = mode else {
+            continue;
+        };
+        let running = has_drm_output(state, node, crtc);
+        if running && current_mode(state, node, crtc) == Some(mode) {
+            continue;
         }
-        Err(e) => {
-            error!("❌ Frame rendering error for {:?}: {}", crtc, e);
+        if !set_pending_mode(state, node, crtc, mode) {
+            restore_output_modes(state, &switched);
+            return None;
+        }
+        if running {
+            switched.push((node, crtc));
         }
     }
-    
-    // Put drm_output back
-    let device = state.udev_data.backends.get_mut(&node).expect("Device must exist");
-    let surface = device.surfaces.get_mut(&(crtc.into())).unwrap();
-    surface.drm_output = Some(drm_output);
+    Some(switched)
 }
 
-/// Device addition handler
-fn device_added(
-    state: &mut DrmCompositorState,
-    node: DrmNode,
-    path: &Path,
+/// Put tested heads back on the mode they are showing
+fn restore_output_modes(state: &mut DrmCompositorState, heads: &[(DrmNode, crtc::Handle)]) {
+    for &(node, crtc) in heads {
+        if let Some(mode) = current_mode(state, node, crtc)
+            && !set_pending_mode(state, node, crtc, mode)
+        {
+            error!("Failed to restore the previous mode on CRTC {:?}", crtc);
+        }
+    }
+}
+
+/// Whether a head has a DrmOutput driving its CRTC
+fn has_drm_output(state: &DrmCompositorState, node: DrmNode, crtc: crtc::Handle) -> bool {
+    state
+        .udev_data
+        .backends
+        .get(&node)
+        .and_then(|device| device.surfaces.get(&crtc.into()))
+        .is_some_and(|surface| surface.drm_output.is_some())
+}
+
+/// The mode a head is currently configured for
+fn current_mode(state: &DrmCompositorState, node: DrmNode, crtc: crtc::Handle) -> Option<drm::control::Mode> {
+    state
+        .udev_data
+        .backends
+        .get(&node)
+        .and_then(|device| device.surfaces.get(&crtc.into()))
+        .map(|surface| surface.mode)
+}
+
+/// Check a client configuration without applying it: every head must resolve
+/// and pass a DRM test commit with its new mode
+fn test_output_configuration(state: &mut DrmCompositorState, config: &OutputConfiguration) -> bool {
+    let Some(resolved) = resolve_output_configuration(state, config) else {
+        return false;
+    };
+    let Some(switched) = test_output_modes(state, &resolved) else {
+        return false;
+    };
+    restore_output_modes(state, &switched);
+    true
+}
+
+/// Apply an output configuration (from a client or a saved profile).
+///
+/// All new modes are test-committed first; only once every head accepts its
+/// mode are outputs disabled, moved and rescaled, so the configuration is
+/// applied completely or not at all.
+fn apply_output_configuration(state: &mut DrmCompositorState, config: OutputConfiguration) -> bool {
+    let Some(resolved) = resolve_output_configuration(state, &config) else {
+        return false;
+    };
+    if test_output_modes(state, &resolved).is_none() {
+        warn!("Output configuration failed the DRM test commit, nothing changed");
+        return false;
+    }
+
+    info!("🖥️  Applying output configuration for {} heads", config.len());
+    for ((name, head), (node, crtc, mode)) in config.into_iter().zip(resolved) {
+        let device = state.udev_data.backends.get_mut(&node).expect("Device must exist");
+        let surface = device.surfaces.get_mut(&crtc.into()).expect("Surface must exist");
+
+        let HeadConfiguration::Enabled { position, transform, scale, .. } = head else {
+            info!("   {} disabled", name);
+            surface.enabled = false;
+            // Dropping the output alone leaves the CRTC showing its last frame
+            if let Some(drm_output) = surface.drm_output.take()
+                && let Err(e) = drm_output.with_compositor(|compositor| compositor.clear())
+            {
+                error!("Failed to turn off {}: {}", name, e);
+            }
+            state.space.unmap_output(&surface.output);
+            continue;
+        };
+        let mode = mode.expect("Enabled heads always resolve to a mode");
+        surface.mode = mode;
+
+        let position = position.unwrap_or_else(|| surface.output.current_location());
+        surface.output.change_current_state(
+            Some(WlMode::from(mode)),
+            transform,
+            scale.map(smithay::output::Scale::Fractional),
+            Some(position),
+        );
+        state.space.map_output(&surface.output, position);
+
+        surface.enabled = true;
+        info!(
+            "   {} -> {}x{}@{:.2}Hz at {:?}",
+            name,
+            mode.size().0,
+            mode.size().1,
+            WlMode::from(mode).refresh as f64 / 1000.0,
+            position
+        );
+    }
+
+    clamp_pointer_to_outputs(state);
+    arrange_layers(state);
+    update_xwayland_scale(state);
+
+    // Mode, position and scale changes damage every output; re-enabled
+    // outputs also get their DrmOutput initialized by this first frame
+    schedule_render_all(state);
+
+    update_surface_scales(state);
+    refresh_output_management(state);
+    true
+}
+
+/// Tell a surface tree which scale and transform its output uses
+fn send_preferred_scale(surface: &WlSurface, output: &Output) {
+    let scale = output.current_scale();
+    let transform = output.current_transform();
+    with_surface_tree_downward(
+        surface,
+        (),
+        |_, _, _| TraversalAction::DoChildren(()),
+        |surface, states, _| {
+            with_fractional_scale(states, |fractional| {
+                fractional.set_preferred_scale(scale.fractional_scale());
+            });
+            send_surface_state(surface, states, scale.integer_scale(), transform);
+        },
+        |_, _, _| true,
+    );
+}
+
+/// Output a surface should take its scale from: the first output its window
+/// is on, or the first output at all for surfaces that aren't mapped yet
+fn output_for_surface(state: &DrmCompositorState, surface: &WlSurface) -> Option<Output> {
+    let mut root = surface.clone();
+    while let Some(parent) = get_parent(&root) {
+        root = parent;
+    }
+
+    state
+        .space
+        .elements()
+        .find(|window| window.toplevel().is_some_and(|t| *t.wl_surface() == root))
+        .and_then(|window| state.space.outputs_for_element(window).into_iter().next())
+        .or_else(|| state.space.outputs().next().cloned())
+}
+
+/// Re-send preferred scales to every mapped window, e.g. after a scale change
+fn update_surface_scales(state: &DrmCompositorState) {
+    for window in state.space.elements() {
+        let Some(toplevel) = window.toplevel() else {
+            continue;
+        };
+        if let Some(output) = state.space.outputs_for_element(window).first() {
+            send_preferred_scale(toplevel.wl_surface(), output);
+        }
+    }
+}
+
+/// Output a window last took its preferred scale from, kept in its user data
+struct ScaleOutput(RefCell<Option<Output>>);
+
+/// Update which outputs every window overlaps (sending wl_surface.enter and
+/// leave), and re-send the preferred scale to windows whose first output changed
+fn refresh_window_outputs(state: &mut DrmCompositorState) {
+    state.space.refresh();
+    for window in state.space.elements() {
+        let Some(toplevel) = window.toplevel() else {
+            continue;
+        };
+        let output = state.space.outputs_for_element(window).into_iter().next();
+        let scale_output = window.user_data().get_or_insert(|| ScaleOutput(RefCell::new(None)));
+        if *scale_output.0.borrow() == output {
+            continue;
+        }
+        if let Some(output) = &output {
+            send_preferred_scale(toplevel.wl_surface(), output);
+        }
+        *scale_output.0.borrow_mut() = output;
+    }
+}
+
+/// Bounding box of all mapped outputs, or None if there are none
+fn layout_bounding_box(space: &Space<Window>) -> Option<Rectangle<i32, Logical>> {
+    space
+        .outputs()
+        .filter_map(|output| space.output_geometry(output))
+        .reduce(|a, b| a.merge(b))
+}
+
+/// Output the pointer starts on: the one at the layout origin, or the first
+/// mapped output if none is
+fn primary_output(space: &Space<Window>) -> Option<Output> {
+    space
+        .outputs()
+        .find(|output| space.output_geometry(output).is_some_and(|geo| geo.loc == (0, 0).into()))
+        .or_else(|| space.outputs().next())
+        .cloned()
+}
+
+/// The part of `output` that layer surfaces (bars, docks) leave free with
+/// their exclusive zones, in global coordinates
+fn usable_area(space: &Space<Window>, output: &Output) -> Option<Rectangle<i32, Logical>> {
+    let output_loc = space.output_geometry(output)?.loc;
+    let mut area = layer_map_for_output(output).non_exclusive_zone();
+    area.loc += output_loc;
+    Some(area)
+}
+
+/// Re-arrange every output's layer surfaces after outputs changed size,
+/// scale or transform
+fn arrange_layers(state: &mut DrmCompositorState) {
+    let outputs: Vec<Output> = state.space.outputs().cloned().collect();
+    for output in outputs {
+        layer_map_for_output(&output).arrange();
+        fit_pseudo_maximized(state, &output);
+    }
+}
+
+/// Keep a pointer position on the union of the output geometries.
+///
+/// Outputs of different sizes leave gaps in the layout's bounding box. A
+/// position in a gap is moved to the nearest point of any output, so the
+/// pointer slides along the edge of the taller output instead of sticking.
+fn clamp_to_outputs(space: &Space<Window>, position: Point<f64, Logical>) -> Point<f64, Logical> {
+    let geometries: Vec<_> = space
+        .outputs()
+        .filter_map(|output| space.output_geometry(output))
+        .collect();
+    clamp_to_areas(&geometries, position)
+}
+
+/// Nearest point to `position` on any of `areas`, or `position` itself if
+/// they are all empty
+fn clamp_to_areas(areas: &[Rectangle<i32, Logical>], position: Point<f64, Logical>) -> Point<f64, Logical> {
+    areas
+        .iter()
+        .filter(|area| !area.is_empty())
+        .map(|area| {
+            let clamped = Point::<f64, Logical>::from((
+                position.x.clamp(area.loc.x as f64, (area.loc.x + area.size.w - 1) as f64),
+                position.y.clamp(area.loc.y as f64, (area.loc.y + area.size.h - 1) as f64),
+            ));
+            let distance = (clamped.x - position.x).powi(2) + (clamped.y - position.y).powi(2);
+            (distance, clamped)
+        })
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, clamped)| clamped)
+        .unwrap_or(position)
+}
+
+/// Send the new pointer position to clients and redraw the cursor
+fn pointer_moved(state: &mut DrmCompositorState, time: u32) {
+    let location = state.pointer_location;
+    let under = input::surface_under(&state.space, location);
+    let seat = state.seat.clone();
+    input::pointer_motion(state, &seat, under.clone(), location, time);
+
+    // A lock or confinement requested by the surface now under the pointer
+    // takes effect once the pointer is inside its region
+    if let (Some((surface, surface_loc)), Some(pointer)) = (under, seat.get_pointer()) {
+        with_pointer_constraint(&surface, &pointer, |constraint| match constraint {
+            Some(constraint) if !constraint.is_active() => {
+                let point = (location - surface_loc).to_i32_round();
+                if constraint.region().is_none_or(|region| region.contains(point)) {
+                    debug!("🔒 Activating pointer constraint");
+                    constraint.activate();
+                }
+            }
+            _ => {}
+        });
+    }
+
+    // The cursor is composited, so it damages whatever it moves over
+    schedule_render_all(state);
+}
+
+/// Handle relative motion from a mouse or touchpad.
+///
+/// Clients always get the raw and accelerated deltas over relative-pointer.
+/// The cursor itself stays put while the surface under it holds a pointer
+/// lock, and doesn't leave the surface (or its region) while confined.
+fn pointer_motion_relative<B, E>(state: &mut DrmCompositorState, event: &E)
+where
+    B: smithay::backend::input::InputBackend,
+    E: smithay::backend::input::PointerMotionEvent<B>,
+{
+    let Some(pointer) = state.seat.get_pointer() else {
+        return;
+    };
+    let location = state.pointer_location;
+    let under = input::surface_under(&state.space, location);
+
+    // An active constraint only applies while the pointer is inside its region
+    let mut locked = false;
+    let mut confined = None;
+    if let Some((surface, surface_loc)) = &under {
+        with_pointer_constraint(surface, &pointer, |constraint| match constraint {
+            Some(constraint) if constraint.is_active() => {
+                let point = (location - *surface_loc).to_i32_round();
+                if !constraint.region().is_none_or(|region| region.contains(point)) {
+                    return;
+                }
+                match &*constraint {
+                    PointerConstraint::Locked(_) => locked = true,
+                    PointerConstraint::Confined(confine) => confined = Some(confine.region().cloned()),
+                }
+            }
+            _ => {}
+        });
+    }
+
+    pointer.relative_motion(
+        state,
+        under.clone(),
+        &RelativeMotionEvent {
+            delta: event.delta(),
+            delta_unaccel: event.delta_unaccel(),
+            utime: event.time(),
+        },
+    );
+    if locked {
+        pointer.frame(state);
+        return;
+    }
+
+    let mut new_location = clamp_to_outputs(&state.space, location + event.delta());
+    if let (Some(region), Some((surface, surface_loc))) = (confined, &under) {
+        // Slide along the edge of the confinement instead of stopping dead
+        let Some(confined_location) = clamp_to_confinement(surface, region.as_ref(), new_location - *surface_loc)
+        else {
+            pointer.frame(state);
+            return;
+        };
+        new_location = confined_location + *surface_loc;
+    }
+
+    trace!("🖱️  Pointer moved: delta=({:.2}, {:.2}) -> pos=({:.1}, {:.1})",
+           event.delta().x, event.delta().y, new_location.x, new_location.y);
+    state.pointer_location = new_location;
+    pointer_moved(state, event.time_msec());
+}
+
+/// Nearest point to `position` (relative to `surface`) inside a confined
+/// pointer's area: the surface itself, cut down to the constraint region if
+/// it has one. None if that area is empty.
+fn clamp_to_confinement(
+    surface: &WlSurface,
+    region: Option<&RegionAttributes>,
+    position: Point<f64, Logical>,
+) -> Option<Point<f64, Logical>> {
+    let size = with_states(surface, |states| {
+        states
+            .data_map
+            .get::<RendererSurfaceStateUserData>()
+            .and_then(|data| data.lock().unwrap().surface_size())
+    })?;
+    clamp_to_region(size, region, position)
+}
+
+/// Nearest point to `position` inside a surface of `size`, cut down to
+/// `region` if there is one
+fn clamp_to_region(
+    size: Size<i32, Logical>,
+    region: Option<&RegionAttributes>,
+    position: Point<f64, Logical>,
+) -> Option<Point<f64, Logical>> {
+    let bounds = Rectangle::from_size(size);
+
+    let areas: Vec<Rectangle<i32, Logical>> = match region {
+        Some(region) => region
+            .rects
+            .iter()
+            .filter(|(kind, _)| matches!(kind, RectangleKind::Add))
+            .filter_map(|(_, rect)| rect.intersection(bounds))
+            .collect(),
+        None => vec![bounds],
+    };
+
+    areas
+        .into_iter()
+        .filter(|area| !area.is_empty())
+        .map(|area| {
+            Point::<f64, Logical>::from((
+                position.x.clamp(area.loc.x as f64, (area.loc.x + area.size.w - 1) as f64),
+                position.y.clamp(area.loc.y as f64, (area.loc.y + area.size.h - 1) as f64),
+            ))
+        })
+        // Subtracted rectangles can still cover the clamped point
+        .filter(|clamped| region.is_none_or(|region| region.contains(clamped.to_i32_round())))
+        .min_by(|a, b| {
+            let distance = |p: &Point<f64, Logical>| (p.x - position.x).powi(2) + (p.y - position.y).powi(2);
+            distance(a).total_cmp(&distance(b))
+        })
+}
+
+/// Re-read the config file and apply what can change at runtime
+fn reload_config(state: &mut DrmCompositorState) {
+    info!("🔄 Reloading config");
+    state.config = Config::load();
+    for device in &mut state.input_devices {
+        libinput_config::apply(device, &state.config.input);
+    }
+    apply_keyboard_config(state);
+    state.clipboard.set_config(&state.config.clipboard);
+}
+
+/// Load the configured keymap (XKB names or a keymap file) and repeat rate
+fn apply_keyboard_config(state: &mut DrmCompositorState) {
+    let Some(keyboard) = state.seat.get_keyboard() else {
+        return;
+    };
+    let config = state.config.keyboard.clone();
+
+    let result = match config.keymap() {
+        Some(keymap) => keyboard
+            .set_keymap_from_string(state, keymap)
+            .map_err(|e| format!("{:?}", e)),
+        None => keyboard
+            .set_xkb_config(
+                state,
+                XkbConfig {
+                    rules: &config.rules,
+                    model: &config.model,
+                    layout: &config.layout,
+                    variant: &config.variant,
+                    options: config.options.clone(),
+                },
+            )
+            .map_err(|e| format!("{:?}", e)),
+    };
+    match result {
+        Ok(()) => info!("⌨️  Keymap loaded (layouts: {:?})", keyboard_layouts(state).0),
+        Err(e) => warn!("Failed to load keymap, keeping the previous one: {}", e),
+    }
+
+    keyboard.change_repeat_info(config.repeat_rate, config.repeat_delay);
+    state.layout_switch_binding = config.switch_layout_binding();
+
+    // The layout list may have changed; tell the shell even if the index didn't
+    state.keyboard_layout = None;
+    check_keyboard_layout(state);
+}
+
+/// Names of the keymap's layouts and the index of the active one
+fn keyboard_layouts(state: &mut DrmCompositorState) -> (Vec<String>, usize) {
+    let Some(keyboard) = state.seat.get_keyboard() else {
+        return (Vec::new(), 0);
+    };
+    keyboard.with_xkb_state(state, |context| {
+        let xkb = context.xkb().lock().unwrap();
+        let layouts = xkb.layouts().map(|layout| xkb.layout_name(layout).to_string()).collect();
+        (layouts, xkb.active_layout().0 as usize)
+    })
+}
+
+/// Switch to the layout at `index`, or cycle to the next one
+fn switch_keyboard_layout(state: &mut DrmCompositorState, index: Option<usize>) {
+    let Some(keyboard) = state.seat.get_keyboard() else {
+        return;
+    };
+    keyboard.with_xkb_state(state, |mut context| match index {
+        Some(index) => context.set_layout(Layout(index as u32)),
+        None => context.cycle_next_layout(),
+    });
+    check_keyboard_layout(state);
+}
+
+/// Announce the active layout over IPC if it changed since we last looked
+fn check_keyboard_layout(state: &mut DrmCompositorState) {
+    let (layouts, active) = keyboard_layouts(state);
+    if state.keyboard_layout == Some(active) {
+        return;
+    }
+    state.keyboard_layout = Some(active);
+
+    let name = layouts.get(active).cloned().unwrap_or_default();
+    info!("⌨️  Keyboard layout: {} ({})", name, active);
+    if let Some(ipc_server) = state.ipc_server.as_mut() {
+        ipc_server.broadcast(&ipc::Event::KeyboardLayoutChanged { name, index: active });
+    }
+}
+
+/// Touchpad gestures: swipes bound in the config drive compositor actions,
+/// everything else is forwarded to the client under the pointer
+fn handle_gesture(state: &mut DrmCompositorState, event: InputEvent<LibinputInputBackend>) {
+    use smithay::backend::input::{GestureBeginEvent, GestureEndEvent, GestureSwipeUpdateEvent};
+
+    match &event {
+        InputEvent::GestureSwipeBegin { event } => {
+            state.compositor_swipe = CompositorSwipe::begin(&state.config.gestures, event.fingers());
+            if state.compositor_swipe.is_some() {
+                debug!("👆 {}-finger swipe claimed by the compositor", event.fingers());
+                return;
+            }
+        }
+        InputEvent::GestureSwipeUpdate { event } => {
+            if let Some(swipe) = state.compositor_swipe.as_mut() {
+                swipe.update(event.delta());
+                return;
+            }
+        }
+        InputEvent::GestureSwipeEnd { event } => {
+            if let Some(swipe) = state.compositor_swipe.take() {
+                if !event.cancelled()
+                    && let Some(action) = swipe.finish(&state.config.gestures)
+                {
+                    run_gesture_action(state, action);
+                }
+                return;
+            }
+        }
+        _ => {}
+    }
+
+    let seat = state.seat.clone();
+    input::forward_gesture(state, &seat, &event);
+}
+
+/// Output a touch device is mapped to: configured by device name or for all
+/// touchscreens, else the built-in panel, else the primary output
+fn touch_output(state: &DrmCompositorState, device: &str) -> Option<Output> {
+    if let Some(wanted) = state.config.touch.output_for(device) {
+        let configured = state
+            .space
+            .outputs()
+            .find(|output| output.name() == wanted || OutputIdentity::of(output) == wanted)
+            .cloned();
+        if configured.is_some() {
+            return configured;
+        }
+        debug!("Touch output {:?} for {:?} is not connected", wanted, device);
+    }
+
+    state
+        .space
+        .outputs()
+        .find(|output| ["eDP", "LVDS", "DSI"].iter().any(|prefix| output.name().starts_with(prefix)))
+        .cloned()
+        .or_else(|| primary_output(&state.space))
+}
+
+/// Global position of a touch, following the output's rotation
+fn touch_location<B, E>(state: &DrmCompositorState, event: &E) -> Option<Point<f64, Logical>>
+where
+    B: smithay::backend::input::InputBackend,
+    E: smithay::backend::input::AbsolutePositionEvent<B>,
+{
+    use smithay::backend::input::Device;
+
+    let output = touch_output(state, &event.device().name())?;
+    let geometry = state.space.output_geometry(&output)?;
+
+    // The touch panel is attached to the display in its native orientation
+    let transform = output.current_transform();
+    let native_size = transform.invert().transform_size(geometry.size);
+    let position = event.position_transformed(native_size);
+    let position = transform.transform_point_in(position, &native_size.to_f64());
+    Some(geometry.loc.to_f64() + position)
+}
+
+/// Touchscreen input: touches go to the surface under the finger
+fn handle_touch(state: &mut DrmCompositorState, event: InputEvent<LibinputInputBackend>) {
+    use smithay::backend::input::{Event as InputEventTrait, TouchEvent};
+
+    let seat = state.seat.clone();
+    match event {
+        InputEvent::TouchDown { event } => {
+            let Some(location) = touch_location(state, &event) else {
+                return;
+            };
+            touch_down(state, event.slot(), location, event.time_msec());
+        }
+        InputEvent::TouchMotion { event } => {
+            let Some(location) = touch_location(state, &event) else {
+                return;
+            };
+            let under = input::surface_under(&state.space, location);
+            input::touch_motion(state, &seat, under, event.slot(), location, event.time_msec());
+        }
+        InputEvent::TouchUp { event } => input::touch_up(state, &seat, event.slot(), event.time_msec()),
+        InputEvent::TouchFrame { .. } => input::touch_frame(state, &seat),
+        InputEvent::TouchCancel { .. } => input::touch_cancel(state, &seat),
+        _ => {}
+    }
+}
+
+/// Start a touch on the surface under it
+fn touch_down(state: &mut DrmCompositorState, slot: TouchSlot, location: Point<f64, Logical>, time: u32) {
+    let seat = state.seat.clone();
+
+    // Touching a window focuses it, like clicking
+    let focus = input::click_focus(&mut state.space, &seat, location);
+    input::set_keyboard_focus(state, &seat, focus);
+    schedule_render_all(state);
+
+    let under = input::surface_under(&state.space, location);
+    input::touch_down(state, &seat, under, slot, location, time);
+}
+
+/// Global position of a tablet tool. Tablets span the whole layout unless
+/// mapped to one output in the config.
+fn tablet_location<B, E>(state: &DrmCompositorState, event: &E) -> Option<Point<f64, Logical>>
+where
+    B: smithay::backend::input::InputBackend,
+    E: smithay::backend::input::TabletToolEvent<B> + smithay::backend::input::Event<B>,
+{
+    use smithay::backend::input::Device;
+
+    let device = event.device().name();
+    let area = state
+        .config
+        .tablet
+        .output_for(&device)
+        .and_then(|wanted| {
+            state
+                .space
+                .outputs()
+                .find(|output| output.name() == wanted || OutputIdentity::of(output) == wanted)
+        })
+        .and_then(|output| state.space.output_geometry(output))
+        .or_else(|| layout_bounding_box(&state.space))?;
+    Some(clamp_to_outputs(&state.space, area.loc.to_f64() + event.position_transformed(area.size)))
+}
+
+/// Graphics tablets: each tool gets its own cursor and talks to clients over
+/// tablet-v2, separately from the pointer
+fn handle_tablet(state: &mut DrmCompositorState, event: InputEvent<LibinputInputBackend>) {
+    use smithay::backend::input::{
+        Event as InputEventTrait, ProximityState, TabletToolButtonEvent, TabletToolEvent,
+        TabletToolProximityEvent, TabletToolTipEvent, TabletToolTipState,
+    };
+    use smithay::utils::SERIAL_COUNTER;
+
+    let tablet_seat = state.seat.tablet_seat();
+    match event {
+        InputEvent::TabletToolProximity { event } => {
+            let Some(location) = tablet_location(state, &event) else {
+                return;
+            };
+            let descriptor = event.tool();
+            let dh = state.display_handle.clone();
+            let tool = tablet_seat.add_tool::<DrmCompositorState>(state, &dh, &descriptor);
+            match event.state() {
+                ProximityState::In => {
+                    debug!("✏️  Tablet tool {:?} in proximity", descriptor.tool_type);
+                    state.tablet_tools.insert(
+                        descriptor,
+                        TabletToolCursor {
+                            location,
+                            image: CursorImageStatus::default_named(),
+                        },
+                    );
+                    let tablet = tablet_seat.get_tablet(&TabletDescriptor::from(&event.device()));
+                    if let (Some(under), Some(tablet)) = (input::surface_under(&state.space, location), tablet) {
+                        tool.proximity_in(location, under, &tablet, SERIAL_COUNTER.next_serial(), event.time_msec());
+                    }
+                }
+                ProximityState::Out => {
+                    debug!("✏️  Tablet tool {:?} out of proximity", descriptor.tool_type);
+                    state.tablet_tools.remove(&descriptor);
+                    tool.proximity_out(event.time_msec());
+                }
+            }
+            schedule_render_all(state);
+        }
+        InputEvent::TabletToolAxis { event } => {
+            let Some(location) = tablet_location(state, &event) else {
+                return;
+            };
+            let tablet = tablet_seat.get_tablet(&TabletDescriptor::from(&event.device()));
+            let tool = tablet_seat.get_tool(&event.tool());
+            let (Some(tablet), Some(tool)) = (tablet, tool) else {
+                return;
+            };
+
+            if event.pressure_has_changed() {
+                tool.pressure(event.pressure());
+            }
+            if event.distance_has_changed() {
+                tool.distance(event.distance());
+            }
+            if event.tilt_has_changed() {
+                tool.tilt(event.tilt());
+            }
+            if event.slider_has_changed() {
+                tool.slider_position(event.slider_position());
+            }
+            if event.rotation_has_changed() {
+                tool.rotation(event.rotation());
+            }
+            if event.wheel_has_changed() {
+                tool.wheel(event.wheel_delta(), event.wheel_delta_discrete());
+            }
+
+            let under = input::surface_under(&state.space, location);
+            tool.motion(location, under, &tablet, SERIAL_COUNTER.next_serial(), event.time_msec());
+
+            if let Some(cursor) = state.tablet_tools.get_mut(&event.tool()) {
+                cursor.location = location;
+            }
+            schedule_render_all(state);
+        }
+        InputEvent::TabletToolTip { event } => {
+            let Some(tool) = tablet_seat.get_tool(&event.tool()) else {
+                return;
+            };
+            match TabletToolTipEvent::tip_state(&event) {
+                TabletToolTipState::Down => {
+                    tool.tip_down(SERIAL_COUNTER.next_serial(), event.time_msec());
+                    // Touching a window with the pen focuses it, like clicking
+                    if let Some(location) = state.tablet_tools.get(&event.tool()).map(|cursor| cursor.location) {
+                        let seat = state.seat.clone();
+                        let focus = input::click_focus(&mut state.space, &seat, location);
+                        input::set_keyboard_focus(state, &seat, focus);
+                        schedule_render_all(state);
+                    }
+                }
+                TabletToolTipState::Up => tool.tip_up(event.time_msec()),
+            }
+        }
+        InputEvent::TabletToolButton { event } => {
+            if let Some(tool) = tablet_seat.get_tool(&event.tool()) {
+                // The libinput event also has the input crate's accessors
+                let button_state = TabletToolButtonEvent::button_state(&event);
+                tool.button(event.button(), button_state, SERIAL_COUNTER.next_serial(), event.time_msec());
+            }
+        }
+        _ => {}
+    }
+}
+
+fn run_gesture_action(state: &mut DrmCompositorState, action: GestureAction) {
+    info!("👆 Gesture action: {:?}", action);
+    match action {
+        GestureAction::SnapLeft => snap_focused_window(state, false),
+        GestureAction::SnapRight => snap_focused_window(state, true),
+    }
+}
+
+/// Tile the keyboard-focused window to the left or right half of its output's
+/// usable area
+fn snap_focused_window(state: &mut DrmCompositorState, right: bool) {
+    let Some(focus) = state.seat.get_keyboard().and_then(|keyboard| keyboard.current_focus()) else {
+        return;
+    };
+    let Some(window) = state
+        .space
+        .elements()
+        .find(|window| window.toplevel().is_some_and(|t| focus.wl_surface().as_deref() == Some(t.wl_surface())))
+        .cloned()
+    else {
+        return;
+    };
+    let Some(geometry) = state
+        .space
+        .outputs_for_element(&window)
+        .into_iter()
+        .next()
+        .or_else(|| primary_output(&state.space))
+        .and_then(|output| usable_area(&state.space, &output))
+    else {
+        return;
+    };
+    // Snapping ends a pseudo-maximize
+    if let Some(saved) = window.user_data().get::<PseudoMaximized>() {
+        saved.0.take();
+    }
+
+    let left_width = geometry.size.w / 2;
+    let (location, width) = if right {
+        (Point::from((geometry.loc.x + left_width, geometry.loc.y)), geometry.size.w - left_width)
+    } else {
+        (geometry.loc, left_width)
+    };
+
+    if let Some(toplevel) = window.toplevel() {
+        toplevel.with_pending_state(|pending| {
+            pending.size = Some((width, geometry.size.h).into());
+            let (tiled_side, other_side) = if right {
+                (xdg_toplevel::State::TiledRight, xdg_toplevel::State::TiledLeft)
+            } else {
+                (xdg_toplevel::State::TiledLeft, xdg_toplevel::State::TiledRight)
+            };
+            pending.states.set(tiled_side);
+            pending.states.unset(other_side);
+            pending.states.set(xdg_toplevel::State::TiledTop);
+            pending.states.set(xdg_toplevel::State::TiledBottom);
+        });
+        toplevel.send_pending_configure();
+    }
+    state.space.map_element(window, location, true);
+    schedule_render_all(state);
+}
+
+/// Size and position a pseudo-maximized window had before, kept in its user data
+struct PseudoMaximized(RefCell<Option<Rectangle<i32, Logical>>>);
+
+/// Pseudo-maximize: fill the usable area of the window's output while
+/// staying a floating window. The client isn't told it's maximized, so it
+/// keeps its own decorations. Unmaximizing restores the previous geometry.
+/// Asking for the state the window is already in changes nothing.
+fn set_pseudo_maximized(state: &mut DrmCompositorState, surface: &ToplevelSurface, maximize: bool) {
+    let Some(window) = state
+        .space
+        .elements()
+        .find(|window| window.toplevel().is_some_and(|t| t == surface))
+        .cloned()
+    else {
+        return;
+    };
+    let Some(location) = state.space.element_location(&window) else {
+        return;
+    };
+    window.user_data().insert_if_missing(|| PseudoMaximized(RefCell::new(None)));
+    let saved = window.user_data().get::<PseudoMaximized>().unwrap();
+
+    if saved.0.borrow().is_some() == maximize {
+        // The client still expects a configure in reply
+        surface.send_configure();
+        return;
+    }
+
+    let target = match saved.0.take() {
+        Some(previous) => previous,
+        None => {
+            let Some(area) = state
+                .space
+                .outputs_for_element(&window)
+                .into_iter()
+                .next()
+                .or_else(|| primary_output(&state.space))
+                .and_then(|output| usable_area(&state.space, &output))
+            else {
+                return;
+            };
+            *saved.0.borrow_mut() = Some(Rectangle::new(location, window.geometry().size));
+            area
+        }
+    };
+    info!("🪟 Pseudo-maximize -> {}x{} at ({}, {})", target.size.w, target.size.h, target.loc.x, target.loc.y);
+
+    surface.with_pending_state(|pending| {
+        pending.size = Some(target.size);
+        pending.states.unset(xdg_toplevel::State::TiledLeft);
+        pending.states.unset(xdg_toplevel::State::TiledRight);
+        pending.states.unset(xdg_toplevel::State::TiledTop);
+        pending.states.unset(xdg_toplevel::State::TiledBottom);
+    });
+    surface.send_pending_configure();
+    state.space.map_element(window, target.loc, true);
+    schedule_render_all(state);
+}
+
+/// Keep pseudo-maximized windows on `output` filling its usable area when
+/// exclusive zones or the output change
+fn fit_pseudo_maximized(state: &mut DrmCompositorState, output: &Output) {
+    let Some(area) = usable_area(&state.space, output) else {
+        return;
+    };
+    let windows: Vec<Window> = state
+        .space
+        .elements()
+        .filter(|window| {
+            window
+                .user_data()
+                .get::<PseudoMaximized>()
+                .is_some_and(|saved| saved.0.borrow().is_some())
+        })
+        .filter(|window| state.space.outputs_for_element(window).contains(output))
+        .cloned()
+        .collect();
+    for window in windows {
+        if let Some(toplevel) = window.toplevel() {
+            toplevel.with_pending_state(|pending| pending.size = Some(area.size));
+            toplevel.send_pending_configure();
+        }
+        state.space.map_element(window, area.loc, false);
+    }
+}
+
+/// Arrange a committed layer surface and send it its first configure
+fn layer_surface_committed(state: &mut DrmCompositorState, output: &Output, surface: &WlSurface) {
+    let initial_configure_sent = with_states(surface, |states| {
+        states
+            .data_map
+            .get::<LayerSurfaceData>()
+            .is_some_and(|data| data.lock().unwrap().initial_configure_sent)
+    });
+
+    let mut map = layer_map_for_output(output);
+    let usable_before = map.non_exclusive_zone();
+    map.arrange();
+    if !initial_configure_sent && let Some(layer) = map.layer_for_surface(surface, WindowSurfaceType::TOPLEVEL) {
+        layer.layer_surface().send_configure();
+    }
+    let usable = map.non_exclusive_zone();
+    drop(map);
+
+    if usable != usable_before {
+        info!(
+            "📐 Usable area of {}: {}x{} at ({}, {})",
+            output.name(),
+            usable.size.w,
+            usable.size.h,
+            usable.loc.x,
+            usable.loc.y
+        );
+        fit_pseudo_maximized(state, output);
+    }
+
+    // An exclusive layer takes the keyboard as soon as it's shown
+    if input::exclusive_layer(&state.space).as_ref() == Some(surface) {
+        let seat = state.seat.clone();
+        let focused = seat.get_keyboard().and_then(|keyboard| keyboard.current_focus());
+        if focused.as_ref().and_then(|focus| focus.wl_surface()).as_deref() != Some(surface) {
+            input::set_keyboard_focus(state, &seat, Some(KeyboardFocusTarget::Surface(surface.clone())));
+        }
+    }
+}
+
+/// Smallest size an interactive resize shrinks a window to when the client
+/// doesn't set its own minimum
+const MIN_WINDOW_SIZE: i32 = 32;
+
+/// Interactive resize started by a client (xdg_toplevel.resize), usually from
+/// a drag on its window border. Shows the resize cursor for the dragged edge
+/// and keeps pointer focus away from clients until the buttons are released.
+struct ResizeSurfaceGrab {
+    start_data: GrabStartData<DrmCompositorState>,
+    window: Window,
+    edges: xdg_toplevel::ResizeEdge,
+    initial_size: smithay::utils::Size<i32, Logical>,
+}
+
+impl ResizeSurfaceGrab {
+    /// (left, right, top, bottom) edges that follow the pointer
+    fn moving_edges(&self) -> (bool, bool, bool, bool) {
+        use xdg_toplevel::ResizeEdge;
+        match self.edges {
+            ResizeEdge::Top => (false, false, true, false),
+            ResizeEdge::Bottom => (false, false, false, true),
+            ResizeEdge::Left => (true, false, false, false),
+            ResizeEdge::Right => (false, true, false, false),
+            ResizeEdge::TopLeft => (true, false, true, false),
+            ResizeEdge::TopRight => (false, true, true, false),
+            ResizeEdge::BottomLeft => (true, false, false, true),
+            ResizeEdge::BottomRight => (false, true, false, true),
+            _ => (false, false, false, false),
+        }
+    }
+}
+
+/// Where a window resized by its top or left edge is anchored, kept in its
+/// user data until the client commits the configure that ends the resize
+#[derive(Debug, Clone, Copy)]
+struct ResizeAnchor {
+    left: bool,
+    top: bool,
+    initial_location: Point<i32, Logical>,
+    initial_size: smithay::utils::Size<i32, Logical>,
+    /// The grab ended; waiting for the client's last commit
+    released: bool,
+}
+
+struct InteractiveResize(RefCell<Option<ResizeAnchor>>);
+
+/// Keep the opposite edge of a window being resized by its top or left edge
+/// in place, using the size the client just committed
+fn resize_committed(state: &mut DrmCompositorState, window: &Window) {
+    let Some(resize) = window.user_data().get::<InteractiveResize>() else {
+        return;
+    };
+    let Some(anchor) = *resize.0.borrow() else {
+        return;
+    };
+
+    let size = window.geometry().size;
+    let mut location = anchor.initial_location;
+    if anchor.left {
+        location.x += anchor.initial_size.w - size.w;
+    }
+    if anchor.top {
+        location.y += anchor.initial_size.h - size.h;
+    }
+    if state.space.element_location(window) != Some(location) {
+        state.space.map_element(window.clone(), location, false);
+    }
+
+    let resizing = window
+        .toplevel()
+        .is_some_and(|toplevel| toplevel.current_state().states.contains(xdg_toplevel::State::Resizing));
+    if anchor.released && !resizing {
+        resize.0.take();
+    }
+}
+
+/// Cursor shape for dragging `edges`
+fn resize_cursor(edges: xdg_toplevel::ResizeEdge) -> CursorIcon {
+    use xdg_toplevel::ResizeEdge;
+    match edges {
+        ResizeEdge::Top => CursorIcon::NResize,
+        ResizeEdge::Bottom => CursorIcon::SResize,
+        ResizeEdge::Left => CursorIcon::WResize,
+        ResizeEdge::Right => CursorIcon::EResize,
+        ResizeEdge::TopLeft => CursorIcon::NwResize,
+        ResizeEdge::TopRight => CursorIcon::NeResize,
+        ResizeEdge::BottomLeft => CursorIcon::SwResize,
+        ResizeEdge::BottomRight => CursorIcon::SeResize,
+        _ => CursorIcon::Default,
+    }
+}
+
+/// Bind an X11 display for X11 apps and export $DISPLAY.
+///
+/// Xwayland itself is started by the first X11 client that connects (see
+/// `spawn_xwayland`), so it costs nothing in sessions without X11 apps.
+fn start_xwayland(state: &mut DrmCompositorState) {
+    let sockets = match X11Sockets::bind() {
+        Ok(sockets) => sockets,
+        Err(e) => {
+            warn!("No X11 display available, X11 apps won't run: {}", e);
+            return;
+        }
+    };
+
+    for listener in &sockets.listeners {
+        let watched = match listener.try_clone() {
+            Ok(watched) => watched,
+            Err(e) => {
+                warn!("Failed to watch the X11 socket: {}", e);
+                return;
+            }
+        };
+        // Never accepted here: Xwayland accepts the waiting client itself
+        let inserted = state.udev_data.loop_handle.insert_source(
+            Generic::new(watched, Interest::READ, CalloopMode::Level),
+            |_, _, state| {
+                if state.xwayland_client.is_none() {
+                    spawn_xwayland(state);
+                }
+                Ok(PostAction::Remove)
+            },
+        );
+        if let Err(e) = inserted {
+            warn!("Failed to watch the X11 socket: {}", e.error);
+            return;
+        }
+    }
+
+    let display_number = sockets.display();
+    state.xwayland_sockets = Some(sockets);
+    // Safety: we are still single-threaded here
+    unsafe { std::env::set_var("DISPLAY", format!(":{}", display_number)) };
+    info!("✅ X11 display ready on DISPLAY=:{}", display_number);
+}
+
+/// Start Xwayland on our X11 display; the window manager attaches once it
+/// reports ready
+fn spawn_xwayland(state: &mut DrmCompositorState) {
+    let Some(sockets) = &state.xwayland_sockets else {
+        return;
+    };
+    let display_number = sockets.display();
+    let process = match xwayland::spawn(sockets) {
+        Ok(process) => process,
+        Err(e) => {
+            warn!("Failed to start Xwayland, X11 apps won't run: {}", e);
+            return;
+        }
+    };
+    let client = match state
+        .display_handle
+        .insert_client(process.wayland_socket, Arc::new(ClientState::default()))
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Failed to add Xwayland as a Wayland client: {}", e);
+            return;
+        }
+    };
+    state.xwayland_client = Some(client.clone());
+    // Before Xwayland creates any surfaces
+    update_xwayland_scale(state);
+    info!("🪟 Starting Xwayland on :{} for the first X11 client", display_number);
+
+    let mut wm_socket = Some(process.wm_socket);
+    let inserted = state.udev_data.loop_handle.insert_source(
+        Generic::new(process.ready, Interest::READ, CalloopMode::Level),
+        move |_, ready, state| {
+            // Xwayland writes the display number once it accepts clients
+            let mut buffer = [0u8; 16];
+            match (&**ready).read(&mut buffer) {
+                Ok(0) => warn!("Xwayland exited during startup"),
+                Ok(_) => {
+                    if let Some(wm_socket) = wm_socket.take() {
+                        xwayland_ready(state, wm_socket, client.clone(), display_number);
+                    }
+                }
+                Err(e) => warn!("Failed to wait for Xwayland: {}", e),
+            }
+            Ok(PostAction::Remove)
+        },
+    );
+    if let Err(e) = inserted {
+        warn!("Failed to watch Xwayland: {}", e.error);
+    }
+}
+
+fn xwayland_ready(state: &mut DrmCompositorState, x11_socket: UnixStream, client: Client, display_number: u32) {
+    let mut wm = match X11Wm::start_wm(state.udev_data.loop_handle.clone(), x11_socket, client.clone()) {
+        Ok(wm) => wm,
+        Err(e) => {
+            error!("Failed to start the X11 window manager: {}", e);
+            return;
+        }
+    };
+
+    // Shown over X11 windows that don't set a cursor of their own
+    let (_, image) = state.cursor.get_image(CursorIcon::Default, 1, Duration::ZERO);
+    if let Err(e) = wm.set_cursor(
+        &image.pixels_rgba,
+        Size::from((image.width as u16, image.height as u16)),
+        Point::from((image.xhot as u16, image.yhot as u16)),
+    ) {
+        warn!("Failed to set the X11 cursor: {}", e);
+    }
+
+    state.xwm = Some(wm);
+    info!("✅ X11 window manager running on :{}", display_number);
+}
+
+/// Let X11 apps render at the largest output scale.
+///
+/// X11 has no per-window scale, so Xwayland gets a client scale: its
+/// buffers and X11 coordinates are `scale` times the logical ones, and
+/// `Xft.dpi` tells toolkits to draw everything that much bigger.
+fn update_xwayland_scale(state: &mut DrmCompositorState) {
+    let Some(client) = state.xwayland_client.clone() else {
+        return;
+    };
+    let scale = state
+        .space
+        .outputs()
+        .map(|output| output.current_scale().integer_scale())
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let old_scale = std::mem::replace(&mut state.xwayland_scale, scale);
+    if scale == old_scale {
+        return;
+    }
+    info!("🪟 X11 apps now render at scale {}", scale);
+
+    if let Some(data) = client.get_data::<ClientState>() {
+        data.compositor_state.set_client_scale(scale as f64);
+    }
+    // Windows keep their logical size and position
+    for window in state.space.elements() {
+        if let Some(surface) = window.x11_surface().filter(|surface| !surface.is_override_redirect()) {
+            let geometry = to_x11(from_x11(surface.geometry(), old_scale), scale);
+            if let Err(e) = surface.configure(geometry) {
+                warn!("Failed to configure X11 window: {}", e);
+            }
+        }
+    }
+
+    let xrdb = Command::new("xrdb").arg("-merge").stdin(Stdio::piped()).spawn();
+    match xrdb {
+        Ok(mut child) => {
+            if let Some(mut stdin) = child.stdin.take()
+                && let Err(e) = writeln!(stdin, "Xft.dpi: {}", 96 * scale)
+            {
+                warn!("Failed to set Xft.dpi: {}", e);
+            }
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => warn!("xrdb not available, X11 toolkits won't scale: {}", e),
+    }
+}
+
+/// X11 coordinates of a logical rectangle
+fn to_x11(rect: Rectangle<i32, Logical>, scale: i32) -> Rectangle<i32, Logical> {
+    Rectangle::new(rect.loc.upscale(scale), rect.size.upscale(scale))
+}
+
+/// Logical coordinates of an X11 rectangle
+fn from_x11(rect: Rectangle<i32, Logical>, scale: i32) -> Rectangle<i32, Logical> {
+    let scale = scale as f64;
+    Rectangle::new(
+        rect.loc.to_f64().downscale(scale).to_i32_round(),
+        rect.size.to_f64().downscale(scale).to_i32_round(),
+    )
+}
+
+fn find_x11_window(space: &Space<Window>, surface: &X11Surface) -> Option<Window> {
+    space.elements().find(|w| w.x11_surface() == Some(surface)).cloned()
+}
+
+impl PointerGrab<DrmCompositorState> for ResizeSurfaceGrab {
+    fn motion(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        _focus: Option<(WlSurface, Point<f64, Logical>)>,
+        event: &MotionEvent,
+    ) {
+        handle.motion(data, None, event);
+
+        let Some(toplevel) = self.window.toplevel() else {
+            return;
+        };
+        let (min_size, max_size) = with_states(toplevel.wl_surface(), |states| {
+            let mut cached = states.cached_state.get::<SurfaceCachedState>();
+            let current = cached.current();
+            (current.min_size, current.max_size)
+        });
+        // A max of 0 means unlimited
+        let clamp = |size: i32, min: i32, max: i32| {
+            let size = size.max(min.max(MIN_WINDOW_SIZE));
+            if max > 0 { size.min(max) } else { size }
+        };
+
+        let delta: Point<i32, Logical> = (event.location - self.start_data.location).to_i32_round();
+        let (left, right, top, bottom) = self.moving_edges();
+        let mut width = self.initial_size.w;
+        let mut height = self.initial_size.h;
+        if left {
+            width -= delta.x;
+        } else if right {
+            width += delta.x;
+        }
+        if top {
+            height -= delta.y;
+        } else if bottom {
+            height += delta.y;
+        }
+        let width = clamp(width, min_size.w, max_size.w);
+        let height = clamp(height, min_size.h, max_size.h);
+
+        toplevel.with_pending_state(|pending| {
+            pending.states.set(xdg_toplevel::State::Resizing);
+            pending.size = Some((width, height).into());
+        });
+        toplevel.send_pending_configure();
+        // The window moves in `resize_committed` once the client uses the new
+        // size; moving it now would jitter while the client catches up
+        schedule_render_all(data);
+    }
+
+    fn relative_motion(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        _focus: Option<(WlSurface, Point<f64, Logical>)>,
+        event: &RelativeMotionEvent,
+    ) {
+        handle.relative_motion(data, None, event);
+    }
+
+    fn button(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        event: &ButtonEvent,
+    ) {
+        handle.button(data, event);
+        if handle.current_pressed().is_empty() {
+            handle.unset_grab(self, data, event.serial, event.time, true);
+        }
+    }
+
+    fn axis(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        details: AxisFrame,
+    ) {
+        handle.axis(data, details);
+    }
+
+    fn frame(&mut self, data: &mut DrmCompositorState, handle: &mut PointerInnerHandle<'_, DrmCompositorState>) {
+        handle.frame(data);
+    }
+
+    fn gesture_swipe_begin(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        event: &GestureSwipeBeginEvent,
+    ) {
+        handle.gesture_swipe_begin(data, event);
+    }
+
+    fn gesture_swipe_update(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        event: &GestureSwipeUpdateEvent,
+    ) {
+        handle.gesture_swipe_update(data, event);
+    }
+
+    fn gesture_swipe_end(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        event: &GestureSwipeEndEvent,
+    ) {
+        handle.gesture_swipe_end(data, event);
+    }
+
+    fn gesture_pinch_begin(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        event: &GesturePinchBeginEvent,
+    ) {
+        handle.gesture_pinch_begin(data, event);
+    }
+
+    fn gesture_pinch_update(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        event: &GesturePinchUpdateEvent,
+    ) {
+        handle.gesture_pinch_update(data, event);
+    }
+
+    fn gesture_pinch_end(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        event: &GesturePinchEndEvent,
+    ) {
+        handle.gesture_pinch_end(data, event);
+    }
+
+    fn gesture_hold_begin(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        event: &GestureHoldBeginEvent,
+    ) {
+        handle.gesture_hold_begin(data, event);
+    }
+
+    fn gesture_hold_end(
+        &mut self,
+        data: &mut DrmCompositorState,
+        handle: &mut PointerInnerHandle<'_, DrmCompositorState>,
+        event: &GestureHoldEndEvent,
+    ) {
+        handle.gesture_hold_end(data, event);
+    }
+
+    fn start_data(&self) -> &GrabStartData<DrmCompositorState> {
+        &self.start_data
+    }
+
+    fn unset(&mut self, data: &mut DrmCompositorState) {
+        if let Some(toplevel) = self.window.toplevel() {
+            toplevel.with_pending_state(|pending| {
+                pending.states.unset(xdg_toplevel::State::Resizing);
+            });
+            toplevel.send_pending_configure();
+        }
+        if let Some(resize) = self.window.user_data().get::<InteractiveResize>()
+            && let Some(anchor) = resize.0.borrow_mut().as_mut()
+        {
+            anchor.released = true;
+        }
+        // The client under the pointer sets its own cursor again on enter
+        data.pointer_element.set_status(CursorImageStatus::default_named());
+        schedule_render_all(data);
+    }
+}
+
+/// Move the pointer back onto the layout after outputs moved or went away
+fn clamp_pointer_to_outputs(state: &mut DrmCompositorState) {
+    let clamped = clamp_to_outputs(&state.space, state.pointer_location);
+    if clamped != state.pointer_location {
+        debug!("🖱️  Pointer moved onto the new layout: ({:.1}, {:.1})", clamped.x, clamped.y);
+        state.pointer_location = clamped;
+    }
+}
+
+/// Remember the current layout for the current set of monitors
+fn save_layout_profile(state: &mut DrmCompositorState) {
+    let outputs = state
+        .udev_data
+        .backends
+        .values()
+        .flat_map(|device| device.surfaces.values())
+        .map(|surface| {
+            let mode = WlMode::from(surface.mode);
+            let position = surface.output.current_location();
+            OutputProfile {
+                identity: OutputIdentity::of(&surface.output),
+                enabled: surface.enabled,
+                width: mode.size.w,
+                height: mode.size.h,
+                refresh: mode.refresh,
+                x: position.x,
+                y: position.y,
+                scale: surface.output.current_scale().fractional_scale(),
+                transform: surface.output.current_transform(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if !outputs.is_empty() {
+        info!("💾 Saving layout profile for {} output(s)", outputs.len());
+        state.layout_profiles.remember(LayoutProfile { outputs });
+    }
+}
+
+/// Re-apply the saved layout for the current set of monitors, if there is one
+fn restore_layout_profile(state: &mut DrmCompositorState) {
+    // (identity, connector name) of every connected output
+    let mut outputs: Vec<(String, String)> = state
+        .udev_data
+        .backends
+        .values()
+        .flat_map(|device| device.surfaces.values())
+        .map(|surface| (OutputIdentity::of(&surface.output), surface.output.name()))
+        .collect();
+    if outputs.is_empty() {
+        return;
+    }
+    outputs.sort();
+
+    let identities: Vec<String> = outputs.iter().map(|(identity, _)| identity.clone()).collect();
+    let Some(profile) = state.layout_profiles.find(&identities) else {
+        info!("No saved layout for {:?}, keeping default placement", identities);
+        return;
+    };
+
+    // Identical monitors without serial numbers share an identity; they are
+    // assigned in connector name order.
+    let config: OutputConfiguration = profile
+        .outputs
+        .iter()
+        .filter_map(|saved| {
+            let index = outputs.iter().position(|(identity, _)| *identity == saved.identity)?;
+            let (_, name) = outputs.remove(index);
+            let head = if saved.enabled {
+                HeadConfiguration::Enabled {
+                    mode: Some(ModeSetting::Custom {
+                        size: (saved.width, saved.height).into(),
+                        refresh: saved.refresh,
+                    }),
+                    position: Some((saved.x, saved.y).into()),
+                    transform: Some(saved.transform),
+                    scale: Some(saved.scale),
+                    adaptive_sync: None,
+                }
+            } else {
+                HeadConfiguration::Disabled
+            };
+            Some((name, head))
+        })
+        .collect();
+
+    info!("📐 Restoring saved layout for {:?}", identities);
+    if !apply_output_configuration(state, config) {
+        warn!("Saved layout could not be applied, keeping default placement");
+    }
+}
+
+impl OutputManagementHandler for DrmCompositorState {
+    fn output_management_state(&mut self) -> &mut OutputManagementState {
+        &mut self.output_management_state
+    }
+
+    fn test_output_configuration(&mut self, config: &OutputConfiguration) -> bool {
+        test_output_configuration(self, config)
+    }
+
+    fn apply_output_configuration(&mut self, config: OutputConfiguration) -> bool {
+        if !apply_output_configuration(self, config) {
+            return false;
+        }
+        save_layout_profile(self);
+        true
+    }
+}
+
+/// Render a frame for a specific surface
+fn render_surface(
+    state: &mut DrmCompositorState,
+    node: DrmNode,
+    crtc: crtc::Handle,
+) {
+    trace!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+    trace!("🎬 RENDER_SURFACE called");
+    trace!("   Node: {}, CRTC: {:?}", node, crtc);
+    trace!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+
+    // Windows that moved onto or off an output since the last frame
+    refresh_window_outputs(state);
+
+    // Disabled outputs (via output management) are not rendered at all
+    let Some(surface) = state.udev_data.backends.get_mut(&node)
+        .and_then(|d| d.surfaces.get_mut(&(crtc.into())))
+    else {
+        return;
+    };
+    if !surface.enabled {
+        debug!("Skipping render for disabled CRTC {:?}", crtc);
+        return;
+    }
+
+    // Only one frame can be in flight; render again once it's on screen
+    if surface.frame_pending {
+        debug!("Frame still pending on CRTC {:?}, deferring render", crtc);
+        surface.redraw_needed = true;
+        return;
+    }
+    surface.redraw_needed = false;
+    
+    // Check if DRM output needs initialization (don't hold device borrow)
+    let needs_init = state.udev_data.backends.get(&node)
+        .and_then(|d| d.surfaces.get(&(crtc.into())))
+        .map(|s| s.drm_output.is_none())
+        .unwrap_or(false);
+    
+    if needs_init {
+        // Initialize DRM output on first render
+        info!("🎨 Initializing DRM output for first render!");
+        
+        // CRITICAL: Get render_node WITHOUT holding mutable borrows
+        let render_node = state.udev_data.backends.get(&node)
+            .and_then(|d| d.surfaces.get(&(crtc.into())))
+            .map(|s| s.render_node.clone())
+            .expect("Surface must exist");
+        
+        // Get renderer (accessing GPU manager - must not have device borrow active)
+        let mut renderer = state.udev_data.gpus.single_renderer(&render_node).unwrap();
+        
+        // NOW get mutable device reference for initialization
+        let device = state.udev_data.backends.get_mut(&node).expect("Device must exist");
+        let surface = device.surfaces.get_mut(&(crtc.into())).unwrap();
+        
+        // Create empty render elements for initialization
+        let render_elements: DrmOutputRenderElements<NuthatchMultiRenderer, NuthatchRenderElements<NuthatchMultiRenderer>> = 
+            DrmOutputRenderElements::new();
+        
+        match device.drm_output_manager.initialize_output(
+            crtc,
+            surface.mode,
+            &[surface.connector],
+            &surface.output,
+            None,  // No plane restrictions for now
+            &mut renderer,
+            &render_elements,
+        ) {
+            Ok(drm_output) => {
+                info!("✅ DRM output initialized successfully!");
+                surface.drm_output = Some(drm_output);
+            }
+            Err(e) => {
+                error!("Failed to initialize DRM output: {}", e);
+                return;
+            }
+        }
+        // device and surface borrows dropped here
+    }
+    
+    // CRITICAL BORROW ORDERING:
+    // 1. Get render_node WITHOUT holding device mutable borrow
+    let render_node = state.udev_data.backends.get(&node)
+        .and_then(|d| d.surfaces.get(&(crtc.into())))
+        .map(|s| s.render_node.clone())
+        .expect("Surface must exist");
+    
+    trace!("🎨 Getting renderer...");
+    // 2. Get renderer (needs access to state.udev_data.gpus)
+    let mut renderer = state.udev_data.gpus.single_renderer(&render_node).unwrap();
+    
+    trace!("✅ Got renderer, now getting output...");
+    // 3. Get mutable device/surface references to extract drm_output
+    let mut drm_output = {
+        let device = state.udev_data.backends.get_mut(&node).expect("Device must exist");
+        let surface = device.surfaces.get_mut(&(crtc.into())).unwrap();
+        // Take ownership of drm_output to release device borrow
+        surface.drm_output.take().expect("DRM output must exist")
+    }; // device borrow dropped here
+    
+    trace!("🎨 Getting renderer...");
+    let mut renderer = state.udev_data.gpus.single_renderer(&state.udev_data.primary_gpu)
+        .expect("Failed to get renderer");
+    trace!("✅ Got renderer, now getting output...");
+
+    trace!("🎨 Rendering frame...");
+    state.frame_count += 1;
+    debug!("   Frame #{}", state.frame_count);
+    
+    // Render at the output's (possibly fractional) scale, relative to its
+    // position in the global layout
+    let output = state.udev_data.backends.get(&node)
+        .and_then(|d| d.surfaces.get(&(crtc.into())))
+        .map(|s| s.output.clone())
+        .expect("Surface must exist");
+    let output_geometry = state.space.output_geometry(&output).unwrap_or_default();
+    let scale = Scale::from(output.current_scale().fractional_scale());
+
+    // Themed cursors play their animation against the compositor's clock.
+    // Track when the next frame of any animated cursor on this output is due.
+    let cursor_time = state.start_time.elapsed();
+    let output_rect = output_geometry.to_f64();
+    let mut next_cursor_frame: Option<Duration> = None;
+
+    // Themed images are loaded at the output's scale (rounded up for
+    // fractional scales) so they stay sharp, and sized to fit the hardware
+    // cursor plane on 1x and 2x outputs
+    let image_scale = output.current_scale().integer_scale().max(1) as u32;
+
+    // Themed image for the shape the pointer currently shows
+    if let CursorImageStatus::Named(icon) = state.pointer_element.status {
+        let (buffer, hotspot) = named_cursor_buffer(
+            &mut state.cursor,
+            &mut state.cursor_buffers,
+            icon,
+            image_scale,
+            cursor_time,
+        );
+        state.pointer_element.set_buffer(buffer, hotspot);
+        if output_rect.contains(state.pointer_location) {
+            next_cursor_frame = state.cursor.next_frame_in(icon, image_scale, cursor_time);
+        }
+    }
+
+    // Render cursor at current pointer location
+    let cursor_pos = (state.pointer_location - output_geometry.loc.to_f64())
+        .to_physical(scale)
+        .to_i32_round();
+    let mut elements: Vec<NuthatchRenderElements<_>> = state.pointer_element
+        .render_elements(&mut renderer, cursor_pos, scale, 1.0);
+    let pointer_ids: Vec<Id> = if output_rect.contains(state.pointer_location) {
+        elements.iter().map(|element| element.id().clone()).collect()
+    } else {
+        Vec::new()
+    };
+
+    // Tablet tool cursors, drawn by the client or from our theme
+    let tools: Vec<(Point<f64, Logical>, CursorImageStatus)> = state
+        .tablet_tools
+        .values()
+        .map(|cursor| (cursor.location, cursor.image.clone()))
+        .collect();
+    for (location, image) in tools {
+        let position = (location - output_geometry.loc.to_f64())
+            .to_physical(scale)
+            .to_i32_round();
+        let (buffer, hotspot) = match image {
+            CursorImageStatus::Named(icon) => {
+                let (buffer, hotspot) = named_cursor_buffer(
+                    &mut state.cursor,
+                    &mut state.cursor_buffers,
+                    icon,
+                    image_scale,
+                    cursor_time,
+                );
+                if output_rect.contains(location) {
+                    let next = state.cursor.next_frame_in(icon, image_scale, cursor_time);
+                    next_cursor_frame = next_cursor_frame.into_iter().chain(next).min();
+                }
+                (Some(buffer), hotspot)
+            }
+            _ => (None, Point::from((0.0, 0.0))),
+        };
+        let tool_element = PointerElement {
+            buffer,
+            hotspot,
+            status: image,
+        };
+        elements.extend(tool_element.render_elements::<NuthatchRenderElements<_>>(&mut renderer, position, scale, 1.0));
+    }
+
+    // Drag-and-drop icon, following the pointer across outputs
+    if let Some(icon) = &state.dnd_icon {
+        let position = (state.pointer_location + icon.offset.to_f64() - output_geometry.loc.to_f64())
+            .to_physical_precise_round(scale);
+        let icon_elements: Vec<WaylandSurfaceRenderElement<_>> = render_elements_from_surface_tree(
+            &mut renderer,
+            &icon.surface,
+            position,
+            scale,
+            1.0,
+            Kind::Unspecified,
+        );
+        elements.extend(icon_elements.into_iter().map(NuthatchRenderElements::from));
+    }
+
+    // Client windows below the cursor
+    match space_render_elements(&mut renderer, [&state.space], &output, 1.0) {
+        Ok(space_elements) => elements.extend(space_elements.into_iter().map(NuthatchRenderElements::from)),
+        Err(e) => warn!("Output {} has no mode, skipping windows: {:?}", output.name(), e),
+    }
+    
+    trace!("🖱️  Rendering cursor at ({}, {}) - {} elements", 
+          cursor_pos.x, cursor_pos.y, elements.len());
+
+    
+    use smithay::backend::drm::compositor::FrameFlags;
+    
+    // The default flags let DrmCompositor put the cursor on the hardware
+    // cursor plane when the image fits it; otherwise it's composited
+    let mut frame_pending = false;
+    let mut cursor_on_plane = None;
+    match drm_output.render_frame(&mut renderer, &elements, CLEAR_COLOR, FrameFlags::DEFAULT) {
+        Ok(render_result) => {
+            trace!("✅ Frame rendered (is_empty: {})", render_result.is_empty);
+            let is_empty = render_result.is_empty;
+            let states = render_result.states;
+
+            // Remember which output each surface was last shown on, so frame
+            // callbacks and presentation feedback go to the right place
+            for window in state.space.elements() {
+                window.with_surfaces(|surface, surface_states| {
+                    update_surface_primary_scanout_output(
+                        surface,
+                        &output,
+                        surface_states,
+                        &states,
+                        default_primary_scanout_output_compare,
+                    );
+                });
+            }
+
+            if !pointer_ids.is_empty() {
+                cursor_on_plane = Some(pointer_ids.iter().all(|id| {
+                    states
+                        .element_render_state(id.clone())
+                        .is_some_and(|render_state| {
+                            render_state.presentation_state == RenderElementPresentationState::ZeroCopy
+                        })
+                }));
+            }
+
+            if is_empty {
+                // Nothing changed on screen, so there is no page flip and no
+                // VBlank. Clients still expect frame callbacks at refresh rate.
+                frame_pending = schedule_estimated_vblank(&state.udev_data.loop_handle, node, crtc, &output);
+            } else {
+                let feedback = take_presentation_feedback(&output, &state.space, &states);
+                match drm_output.queue_frame(Some(feedback)) {
+                    Ok(_) => {
+                        trace!("✅ Frame queued - waiting for next VBlank");
+                        frame_pending = true;
+                    }
+                    Err(e) => {
+                        error!("❌ Failed to queue frame for {:?}: {}", crtc, e);
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            error!("❌ Frame rendering error for {:?}: {}", crtc, e);
+        }
+    }
+    
+    // Put drm_output back
+    let device = state.udev_data.backends.get_mut(&node).expect("Device must exist");
+    let surface = device.surfaces.get_mut(&(crtc.into())).unwrap();
+    surface.drm_output = Some(drm_output);
+    surface.frame_pending = frame_pending;
+    if let Some(on_plane) = cursor_on_plane {
+        if surface.cursor_on_plane != Some(on_plane) {
+            if on_plane {
+                info!("🖱️  Cursor on hardware plane for {}", output.name());
+            } else {
+                info!("🖱️  Cursor composited on {}", output.name());
+            }
+        }
+        surface.cursor_on_plane = Some(on_plane);
+    }
+
+    if let Some(delay) = next_cursor_frame {
+        schedule_cursor_animation(
+            &state.udev_data.loop_handle,
+            &mut state.animated_cursor_outputs,
+            &output,
+            delay,
+        );
+    }
+}
+
+/// Render `output` again when the next frame of an animated cursor on it is
+/// due. Only outputs showing an animation get these timers, and only one at
+/// a time, so static cursors never cause extra frames.
+fn schedule_cursor_animation(
+    loop_handle: &LoopHandle<'static, DrmCompositorState>,
+    animated_cursor_outputs: &mut HashSet<String>,
+    output: &Output,
+    delay: Duration,
+) {
+    if !animated_cursor_outputs.insert(output.name()) {
+        return;
+    }
+    let timer_output = output.clone();
+    let result = loop_handle.insert_source(Timer::from_duration(delay), move |_, _, state| {
+        state.animated_cursor_outputs.remove(&timer_output.name());
+        schedule_render_output(state, &timer_output);
+        TimeoutAction::Drop
+    });
+    if let Err(e) = result {
+        warn!("Failed to schedule cursor animation on {}: {}", output.name(), e);
+        animated_cursor_outputs.remove(&output.name());
+    }
+}
+
+/// Refresh interval of `output`'s mode, if it reports one. Some virtual and
+/// broken connectors report a refresh of 0.
+fn refresh_interval(output: &Output) -> Option<Duration> {
+    output
+        .current_mode()
+        .filter(|mode| mode.refresh > 0)
+        .map(|mode| Duration::from_secs_f64(1_000f64 / mode.refresh as f64))
+}
+
+/// Refresh interval to schedule frames with, assuming 60 Hz for outputs that
+/// don't report one
+fn frame_duration(output: &Output) -> Duration {
+    refresh_interval(output).unwrap_or(Duration::from_secs_f64(1.0 / 60.0))
+}
+
+/// Stand-in for a VBlank when a frame had no damage and wasn't submitted
+fn schedule_estimated_vblank(
+    loop_handle: &LoopHandle<'static, DrmCompositorState>,
+    node: DrmNode,
+    crtc: crtc::Handle,
+    output: &Output,
+) -> bool {
+    let result = loop_handle.insert_source(
+        Timer::from_duration(frame_duration(output)),
+        move |_, _, state| {
+            frame_finished(state, node, crtc);
+            TimeoutAction::Drop
+        },
+    );
+    if let Err(e) = result {
+        error!("Failed to schedule estimated VBlank for {:?}: {}", crtc, e);
+        return false;
+    }
+    true
+}
+
+/// The previous frame on `crtc` is done (real or estimated VBlank): let clients
+/// draw their next frame, and render again if anything was damaged meanwhile
+fn frame_finished(state: &mut DrmCompositorState, node: DrmNode, crtc: crtc::Handle) {
+    let Some(surface) = state.udev_data.backends.get_mut(&node)
+        .and_then(|d| d.surfaces.get_mut(&(crtc.into())))
+    else {
+        return;
+    };
+    surface.frame_pending = false;
+    let redraw_needed = surface.redraw_needed;
+    let output = surface.output.clone();
+
+    send_frame_callbacks(state, &output);
+
+    if redraw_needed {
+        render_surface(state, node, crtc);
+    } else {
+        trace!("No damage on {}, going idle", output.name());
+    }
+}
+
+/// Request a new frame on one output. Rendering happens from an idle callback
+/// so several damage sources in one dispatch produce a single frame, and is
+/// deferred until the previous frame has been presented.
+fn schedule_render(state: &mut DrmCompositorState, node: DrmNode, crtc: crtc::Handle) {
+    let Some(surface) = state.udev_data.backends.get_mut(&node)
+        .and_then(|d| d.surfaces.get_mut(&(crtc.into())))
+    else {
+        return;
+    };
+    if !surface.enabled {
+        return;
+    }
+
+    surface.redraw_needed = true;
+    if surface.frame_pending || surface.render_scheduled {
+        return;
+    }
+
+    surface.render_scheduled = true;
+    state.udev_data.loop_handle.insert_idle(move |state| {
+        if let Some(surface) = state.udev_data.backends.get_mut(&node)
+            .and_then(|d| d.surfaces.get_mut(&(crtc.into())))
+        {
+            surface.render_scheduled = false;
+        }
+        render_surface(state, node, crtc);
+    });
+}
+
+/// Request a new frame on `output`
+fn schedule_render_output(state: &mut DrmCompositorState, output: &Output) {
+    let target = state.udev_data.backends.iter().find_map(|(node, device)| {
+        device
+            .surfaces
+            .iter()
+            .find(|(_, surface)| surface.output == *output)
+            .map(|(crtc, _)| (*node, crtc_handle(*crtc)))
+    });
+    if let Some((node, crtc)) = target {
+        schedule_render(state, node, crtc);
+    }
+}
+
+/// Request a new frame on every output
+fn schedule_render_all(state: &mut DrmCompositorState) {
+    let targets: Vec<(DrmNode, crtc::Handle)> = state
+        .udev_data
+        .backends
+        .iter()
+        .flat_map(|(node, device)| device.surfaces.keys().map(move |crtc| (*node, crtc_handle(*crtc))))
+        .collect();
+    for (node, crtc) in targets {
+        schedule_render(state, node, crtc);
+    }
+}
+
+/// Request a new frame on the outputs showing `surface`
+fn schedule_render_for_surface(state: &mut DrmCompositorState, surface: &WlSurface) {
+    let mut root = surface.clone();
+    while let Some(parent) = get_parent(&root) {
+        root = parent;
+    }
+
+    let outputs = state
+        .space
+        .elements()
+        .find(|window| window.toplevel().is_some_and(|t| *t.wl_surface() == root))
+        .map(|window| state.space.outputs_for_element(window))
+        .unwrap_or_default();
+
+    if outputs.is_empty() {
+        schedule_render_all(state);
+    } else {
+        for output in outputs {
+            schedule_render_output(state, &output);
+        }
+    }
+}
+
+/// Collect presentation feedback for every surface shown on `output`
+fn take_presentation_feedback(
+    output: &Output,
+    space: &Space<Window>,
+    render_element_states: &RenderElementStates,
+) -> OutputPresentationFeedback {
+    let mut output_presentation_feedback = OutputPresentationFeedback::new(output);
+
+    for window in space.elements() {
+        if space.outputs_for_element(window).contains(output) {
+            window.take_presentation_feedback(
+                &mut output_presentation_feedback,
+                surface_primary_scanout_output,
+                |surface, _| surface_presentation_feedback_flags_from_states(surface, render_element_states),
+            );
+        }
+    }
+    for layer in layer_map_for_output(output).layers() {
+        layer.take_presentation_feedback(
+            &mut output_presentation_feedback,
+            surface_primary_scanout_output,
+            |surface, _| surface_presentation_feedback_flags_from_states(surface, render_element_states),
+        );
+    }
+
+    output_presentation_feedback
+}
+
+/// Report a completed page flip to `wp_presentation` clients
+fn send_presentation_feedback(
+    feedback: &mut OutputPresentationFeedback,
+    output: &Output,
+    metadata: &Option<DrmEventMetadata>,
+    clock: &Clock<Monotonic>,
+) {
+    // Prefer the kernel's flip timestamp; fall back to "now" if it used the realtime clock
+    let (time, flags) = match metadata.as_ref().map(|metadata| metadata.time) {
+        Some(DrmEventTime::Monotonic(time)) => (
+            time.into(),
+            wp_presentation_feedback::Kind::Vsync
+                | wp_presentation_feedback::Kind::HwClock
+                | wp_presentation_feedback::Kind::HwCompletion,
+        ),
+        _ => (clock.now(), wp_presentation_feedback::Kind::Vsync),
+    };
+    let sequence = metadata.as_ref().map(|metadata| metadata.sequence).unwrap_or(0);
+    // frame_duration's 60 Hz guess is only good enough for scheduling
+    let refresh = refresh_interval(output).map_or(Refresh::Unknown, Refresh::fixed);
+
+    feedback.presented(time, refresh, sequence as u64, flags);
+}
+
+/// Themed image for a named cursor shape at `time`, drawn for outputs with
+/// the given integer scale, and its hotspot. Uploaded once per animation
+/// frame and scale; switching between frames' buffers only damages the
+/// cursor's own rectangle.
+fn named_cursor_buffer(
+    cursor: &mut crate::cursor::Cursor,
+    cursor_buffers: &mut CursorBuffers,
+    icon: CursorIcon,
+    image_scale: u32,
+    time: Duration,
+) -> (MemoryRenderBuffer, Point<f64, Logical>) {
+    let base_size = cursor.size();
+    let (frame, cursor_image) = cursor.get_image(icon, image_scale, time);
+    if let Some(cached) = cursor_buffers.get(&(icon, frame, image_scale)) {
+        return cached.clone();
+    }
+
+    let buffer_scale = crate::cursor::buffer_scale(cursor_image, base_size);
+    let buffer = MemoryRenderBuffer::from_slice(
+        &cursor_image.pixels_rgba,
+        Fourcc::Argb8888,
+        (cursor_image.width as i32, cursor_image.height as i32),
+        buffer_scale,
+        Transform::Normal,
+        None,
+    );
+    let hotspot = Point::from((
+        cursor_image.xhot as f64 / buffer_scale as f64,
+        cursor_image.yhot as f64 / buffer_scale as f64,
+    ));
+    debug!(
+        "Loaded cursor {:?} frame {} ({}x{}) at scale {} for output scale {}",
+        icon.name(),
+        frame,
+        cursor_image.width,
+        cursor_image.height,
+        buffer_scale,
+        image_scale
+    );
+    cursor_buffers.insert((icon, frame, image_scale), (buffer.clone(), hotspot));
+    (buffer, hotspot)
+}
+
+/// Send frame callbacks to every window whose primary output is `output`
+fn send_frame_callbacks(state: &DrmCompositorState, output: &Output) {
+    let time = state.clock.now();
+    for window in state.space.elements() {
+        if state.space.outputs_for_element(window).contains(output) {
+            window.send_frame(output, time, Some(Duration::ZERO), surface_primary_scanout_output);
+        }
+    }
+    for layer in layer_map_for_output(output).layers() {
+        layer.send_frame(output, time, Some(Duration::ZERO), surface_primary_scanout_output);
+    }
+
+    // Animated client cursors and drag icons redraw on frame callbacks from
+    // the output they're on
+    let on_output = state
+        .space
+        .output_geometry(output)
+        .is_some_and(|geometry| geometry.to_f64().contains(state.pointer_location));
+    if on_output {
+        if let CursorImageStatus::Surface(surface) = &state.pointer_element.status {
+            send_frames_surface_tree(surface, output, time, Some(Duration::ZERO), |_, _| Some(output.clone()));
+        }
+        if let Some(icon) = &state.dnd_icon {
+            send_frames_surface_tree(&icon.surface, output, time, Some(Duration::ZERO), |_, _| Some(output.clone()));
+        }
+    }
+}
+
+/// Device addition handler
+fn device_added(
+    state: &mut DrmCompositorState,
+    node: DrmNode,
+    path: &Path,
 ) -> Result<(), DeviceAddError> {
     info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     info!("📍 DEVICE_ADDED called");
@@ -1012,25 +3565,33 @@ fn device_added(
         .loop_handle
         .insert_source(
             notifier,
-            move |event, _metadata, data: &mut DrmCompositorState| match event {
+            move |event, metadata, data: &mut DrmCompositorState| match event {
                 DrmEvent::VBlank(crtc) => {
-                    info!("🎬 VBlank event for CRTC {:?}", crtc);
+                    trace!("🎬 VBlank event for CRTC {:?}", crtc);
                     
                     // CRITICAL: Mark previous frame as submitted to release buffer back to swapchain
                     let device = data.udev_data.backends.get_mut(&node).expect("Device must exist");
-                    let surface = device.surfaces.get_mut(&(crtc.into())).expect("Surface must exist");
+                    let Some(surface) = device.surfaces.get_mut(&(crtc.into())) else {
+                        // Connector was unplugged while a frame was in flight
+                        debug!("   VBlank for removed surface, ignoring");
+                        return;
+                    };
                     
                     if let Some(ref mut drm_output) = surface.drm_output {
                         match drm_output.frame_submitted() {
-                            Ok(_) => info!("   Frame submitted, buffer released to swapchain"),
+                            Ok(feedback) => {
+                                trace!("   Frame submitted, buffer released to swapchain");
+                                if let Some(mut feedback) = feedback.flatten() {
+                                    send_presentation_feedback(&mut feedback, &surface.output, metadata, &data.clock);
+                                }
+                            }
                             Err(e) => error!("   Failed to mark frame as submitted: {:?}", e),
                         }
                     }
-                    
-                    // Now render the next frame
-                    info!("   Triggering render for next frame...");
-                    render_surface(data, node, crtc);
-                    info!("   Render_surface completed");
+
+                    // The frame is on screen: let clients draw the next one,
+                    // and render again only if something was damaged
+                    frame_finished(data, node, crtc);
                 }
                 DrmEvent::Error(error) => {
                     error!("DRM error: {:?}", error);
@@ -1151,6 +3712,7 @@ enum DeviceAddError {
 // ============================================================================
 
 // Client state for tracking per-client data
+#[derive(Default)]
 pub struct ClientState {
     pub compositor_state: CompositorClientState,
 }
@@ -1175,7 +3737,65 @@ impl CompositorHandler for DrmCompositorState {
 
     fn commit(&mut self, surface: &WlSurface) {
         trace!("Surface committed: {:?}", surface);
-        // TODO: Handle surface commits - update window state
+        on_commit_buffer_handler::<Self>(surface);
+        if let Some(icon) = &mut self.dnd_icon {
+            icon.commit(surface);
+        }
+        schedule_render_for_surface(self, surface);
+
+        // Layer surfaces are placed by their output's layer map
+        let layer_output = self
+            .space
+            .outputs()
+            .find(|output| {
+                layer_map_for_output(output)
+                    .layer_for_surface(surface, WindowSurfaceType::TOPLEVEL)
+                    .is_some()
+            })
+            .cloned();
+        if let Some(output) = layer_output {
+            layer_surface_committed(self, &output, surface);
+        }
+
+        if !is_sync_subsurface(surface) {
+            let mut root = surface.clone();
+            while let Some(parent) = get_parent(&root) {
+                root = parent;
+            }
+            if let Some(window) = self
+                .space
+                .elements()
+                .find(|w| w.wl_surface().is_some_and(|s| *s == root))
+            {
+                window.on_commit();
+            }
+        }
+
+        let resized = self
+            .space
+            .elements()
+            .find(|w| w.toplevel().is_some_and(|t| t.wl_surface() == surface))
+            .cloned();
+        if let Some(window) = resized {
+            resize_committed(self, &window);
+        }
+
+        // Toplevels need an initial configure before they attach a buffer
+        if let Some(window) = self
+            .space
+            .elements()
+            .find(|w| w.toplevel().is_some_and(|t| t.wl_surface() == surface))
+        {
+            let initial_configure_sent = with_states(surface, |states| {
+                states
+                    .data_map
+                    .get::<XdgToplevelSurfaceData>()
+                    .is_some_and(|data| data.lock().unwrap().initial_configure_sent)
+            });
+            if !initial_configure_sent && let Some(toplevel) = window.toplevel() {
+                toplevel.send_configure();
+            }
+        }
     }
 }
 
@@ -1185,14 +3805,98 @@ impl XdgShellHandler for DrmCompositorState {
         &mut self.xdg_shell_state
     }
 
-    fn new_toplevel(&mut self, _surface: ToplevelSurface) {
+    fn new_toplevel(&mut self, surface: ToplevelSurface) {
         info!("New toplevel window created");
-        // TODO: Add window to space
+        let window = Window::new_wayland_window(surface);
+        self.space.map_element(window, (0, 0), false);
     }
 
-    fn toplevel_destroyed(&mut self, _surface: ToplevelSurface) {
+    fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
         info!("Toplevel window destroyed");
-        // TODO: Remove window from space
+        let window = self
+            .space
+            .elements()
+            .find(|w| w.toplevel().is_some_and(|t| t == &surface))
+            .cloned();
+        if let Some(window) = window {
+            self.space.unmap_elem(&window);
+        }
+    }
+
+    fn resize_request(
+        &mut self,
+        surface: ToplevelSurface,
+        seat: WlSeat,
+        serial: smithay::utils::Serial,
+        edges: xdg_toplevel::ResizeEdge,
+    ) {
+        let Some(seat) = Seat::<Self>::from_resource(&seat) else {
+            return;
+        };
+        let Some(pointer) = seat.get_pointer() else {
+            return;
+        };
+        // Only honour requests made in response to a button press on this window
+        if !pointer.has_grab(serial) {
+            return;
+        }
+        let Some(start_data) = pointer.grab_start_data() else {
+            return;
+        };
+        if !start_data
+            .focus
+            .as_ref()
+            .is_some_and(|(focus, _)| focus.id().same_client_as(&surface.wl_surface().id()))
+        {
+            return;
+        }
+        let Some(window) = self
+            .space
+            .elements()
+            .find(|w| w.toplevel().is_some_and(|t| t == &surface))
+            .cloned()
+        else {
+            return;
+        };
+        let Some(initial_location) = self.space.element_location(&window) else {
+            return;
+        };
+
+        debug!("Interactive resize from {:?}", edges);
+        // A window resized by hand is no longer pseudo-maximized
+        if let Some(saved) = window.user_data().get::<PseudoMaximized>() {
+            saved.0.take();
+        }
+        let grab = ResizeSurfaceGrab {
+            start_data,
+            initial_size: window.geometry().size,
+            window: window.clone(),
+            edges,
+        };
+        let (left, _, top, _) = grab.moving_edges();
+        let anchor = (left || top).then_some(ResizeAnchor {
+            left,
+            top,
+            initial_location,
+            initial_size: grab.initial_size,
+            released: false,
+        });
+        *window
+            .user_data()
+            .get_or_insert(|| InteractiveResize(RefCell::new(None)))
+            .0
+            .borrow_mut() = anchor;
+        self.pointer_element.set_status(CursorImageStatus::Named(resize_cursor(edges)));
+        pointer.set_grab(self, grab, serial, Focus::Clear);
+        schedule_render_all(self);
+    }
+
+    fn maximize_request(&mut self, surface: ToplevelSurface) {
+        set_pseudo_maximized(self, &surface, true);
+    }
+
+    fn unmaximize_request(&mut self, surface: ToplevelSurface) {
+        set_pseudo_maximized(self, &surface, false);
     }
 
     fn new_popup(&mut self, _surface: PopupSurface, _positioner: PositionerState) {
@@ -1215,6 +3919,53 @@ impl XdgShellHandler for DrmCompositorState {
     }
 }
 
+// Layer shell handler - the desktop shell's wallpaper, dock and bars
+impl WlrLayerShellHandler for DrmCompositorState {
+    fn shell_state(&mut self) -> &mut WlrLayerShellState {
+        &mut self.layer_shell_state
+    }
+
+    fn new_layer_surface(
+        &mut self,
+        surface: WlrLayerSurface,
+        output: Option<WlOutput>,
+        layer: Layer,
+        namespace: String,
+    ) {
+        // Without a requested output, the one under the pointer
+        let output = output
+            .as_ref()
+            .and_then(Output::from_resource)
+            .or_else(|| self.space.output_under(self.pointer_location).next().cloned())
+            .or_else(|| primary_output(&self.space));
+        let Some(output) = output else {
+            warn!("No output for {:?} layer surface {:?}", layer, namespace);
+            surface.send_close();
+            return;
+        };
+        info!("New {:?} layer surface {:?} on {}", layer, namespace, output.name());
+        if let Err(e) = layer_map_for_output(&output).map_layer(&LayerSurface::new(surface, namespace)) {
+            warn!("Failed to map layer surface: {}", e);
+        }
+    }
+
+    fn layer_destroyed(&mut self, surface: WlrLayerSurface) {
+        let outputs: Vec<Output> = self.space.outputs().cloned().collect();
+        for output in outputs {
+            let mut map = layer_map_for_output(&output);
+            let Some(layer) = map.layers().find(|layer| layer.layer_surface() == &surface).cloned() else {
+                continue;
+            };
+            info!("Layer surface {:?} destroyed", layer.namespace());
+            map.unmap_layer(&layer);
+            drop(map);
+            // Its exclusive zone is gone
+            fit_pseudo_maximized(self, &output);
+        }
+        schedule_render_all(self);
+    }
+}
+
 // SHM handler - handles shared memory buffers
 impl ShmHandler for DrmCompositorState {
     fn shm_state(&self) -> &ShmState {
@@ -1229,9 +3980,98 @@ impl BufferHandler for DrmCompositorState {
     }
 }
 
+/// Keyboard focus: a Wayland surface, or an X11 window, which also needs
+/// X11's input focus. smithay's `X11Surface` takes care of both.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyboardFocusTarget {
+    Surface(WlSurface),
+    X11(X11Surface),
+}
+
+impl FocusTarget for KeyboardFocusTarget {
+    fn from_surface(surface: WlSurface) -> Self {
+        Self::Surface(surface)
+    }
+
+    fn from_window(window: &Window) -> Option<Self> {
+        match window.x11_surface() {
+            Some(surface) => Some(Self::X11(surface.clone())),
+            None => window.toplevel().map(|toplevel| Self::Surface(toplevel.wl_surface().clone())),
+        }
+    }
+}
+
+impl IsAlive for KeyboardFocusTarget {
+    fn alive(&self) -> bool {
+        match self {
+            Self::Surface(surface) => surface.alive(),
+            Self::X11(surface) => surface.alive(),
+        }
+    }
+}
+
+impl WaylandFocus for KeyboardFocusTarget {
+    fn wl_surface(&self) -> Option<Cow<'_, WlSurface>> {
+        match self {
+            Self::Surface(surface) => Some(Cow::Borrowed(surface)),
+            Self::X11(surface) => surface.wl_surface().map(Cow::Owned),
+        }
+    }
+}
+
+impl KeyboardTarget<DrmCompositorState> for KeyboardFocusTarget {
+    fn enter(
+        &self,
+        seat: &Seat<DrmCompositorState>,
+        data: &mut DrmCompositorState,
+        keys: Vec<KeysymHandle<'_>>,
+        serial: Serial,
+    ) {
+        match self {
+            Self::Surface(surface) => KeyboardTarget::enter(surface, seat, data, keys, serial),
+            Self::X11(surface) => KeyboardTarget::enter(surface, seat, data, keys, serial),
+        }
+    }
+
+    fn leave(&self, seat: &Seat<DrmCompositorState>, data: &mut DrmCompositorState, serial: Serial) {
+        match self {
+            Self::Surface(surface) => KeyboardTarget::leave(surface, seat, data, serial),
+            Self::X11(surface) => KeyboardTarget::leave(surface, seat, data, serial),
+        }
+    }
+
+    fn key(
+        &self,
+        seat: &Seat<DrmCompositorState>,
+        data: &mut DrmCompositorState,
+        key: KeysymHandle<'_>,
+        state: smithay::backend::input::KeyState,
+        serial: Serial,
+        time: u32,
+    ) {
+        match self {
+            Self::Surface(surface) => KeyboardTarget::key(surface, seat, data, key, state, serial, time),
+            Self::X11(surface) => KeyboardTarget::key(surface, seat, data, key, state, serial, time),
+        }
+    }
+
+    fn modifiers(
+        &self,
+        seat: &Seat<DrmCompositorState>,
+        data: &mut DrmCompositorState,
+        modifiers: ModifiersState,
+        serial: Serial,
+    ) {
+        match self {
+            Self::Surface(surface) => KeyboardTarget::modifiers(surface, seat, data, modifiers, serial),
+            Self::X11(surface) => KeyboardTarget::modifiers(surface, seat, data, modifiers, serial),
+        }
+    }
+}
+
 // Seat handler - handles input seat management
 impl SeatHandler for DrmCompositorState {
-    type KeyboardFocus = WlSurface;
+    type KeyboardFocus = KeyboardFocusTarget;
     type PointerFocus = WlSurface;
     type TouchFocus = WlSurface;
 
@@ -1239,13 +4079,33 @@ impl SeatHandler for DrmCompositorState {
         &mut self.seat_state
     }
 
-    fn focus_changed(&mut self, _seat: &smithay::input::Seat<Self>, _focused: Option<&Self::KeyboardFocus>) {
+    fn focus_changed(&mut self, seat: &smithay::input::Seat<Self>, focused: Option<&Self::KeyboardFocus>) {
         debug!("Keyboard focus changed");
+        // Only the focused client may paste
+        let client = focused
+            .and_then(|focus| focus.wl_surface())
+            .and_then(|surface| self.display_handle.get_client(surface.id()).ok());
+        set_data_device_focus(&self.display_handle, seat, client.clone());
+        set_primary_focus(&self.display_handle, seat, client);
+
+        // A focused X11 window is activated and raised in X11's stacking
+        // order too; X11 input focus moves in `KeyboardFocusTarget::enter`
+        if let Some(KeyboardFocusTarget::X11(window)) = focused {
+            if let Err(e) = window.set_activated(true) {
+                warn!("Failed to activate X11 window: {}", e);
+            }
+            if let Some(xwm) = self.xwm.as_mut()
+                && let Err(e) = xwm.raise_window(window)
+            {
+                warn!("Failed to raise X11 window: {}", e);
+            }
+        }
     }
 
-    fn cursor_image(&mut self, _seat: &smithay::input::Seat<Self>, _image: smithay::input::pointer::CursorImageStatus) {
-        trace!("Cursor image changed");
-        // TODO: Update cursor rendering
+    fn cursor_image(&mut self, _seat: &smithay::input::Seat<Self>, image: CursorImageStatus) {
+        trace!("Cursor image changed: {:?}", image);
+        self.pointer_element.set_status(image);
+        schedule_render_all(self);
     }
 }
 
@@ -1256,21 +4116,556 @@ impl DataDeviceHandler for DrmCompositorState {
     }
 }
 
-impl ClientDndGrabHandler for DrmCompositorState {}
+// Drag-and-drop between clients; smithay's grab moves the pointer focus
+// across windows and outputs, we only draw the icon
+impl ClientDndGrabHandler for DrmCompositorState {
+    fn started(&mut self, _source: Option<WlDataSource>, icon: Option<WlSurface>, _seat: Seat<Self>) {
+        debug!("Drag-and-drop started");
+        self.dnd_icon = icon.map(DndIcon::new);
+        schedule_render_all(self);
+    }
+
+    fn dropped(&mut self, target: Option<WlSurface>, validated: bool, _seat: Seat<Self>) {
+        debug!(
+            "Drag-and-drop {} on {:?}",
+            if validated { "dropped" } else { "cancelled" },
+            target.map(|surface| surface.id())
+        );
+        self.dnd_icon = None;
+        schedule_render_all(self);
+    }
+}
+
+// We never start drags ourselves
 impl ServerDndGrabHandler for DrmCompositorState {}
 
-// Selection handler - handles selection (clipboard)
+// Primary selection handler - middle-click paste
+impl PrimarySelectionHandler for DrmCompositorState {
+    fn primary_selection_state(&self) -> &PrimarySelectionState {
+        &self.primary_selection_state
+    }
+}
+
+// Data control handlers - clipboard managers (wlr and ext protocol versions)
+impl DataControlHandler for DrmCompositorState {
+    fn data_control_state(&self) -> &DataControlState {
+        &self.data_control_state
+    }
+}
+
+impl ExtDataControlHandler for DrmCompositorState {
+    fn data_control_state(&self) -> &ExtDataControlState {
+        &self.ext_data_control_state
+    }
+}
+
+// Selection handler - handles selection (clipboard and primary selection),
+// whichever protocol set it
 impl SelectionHandler for DrmCompositorState {
-    type SelectionUserData = ();
+    type SelectionUserData = OfferedSelection;
+
+    fn new_selection(&mut self, ty: SelectionTarget, source: Option<SelectionSource>, seat: Seat<Self>) {
+        let mime_types = source.map(|source| source.mime_types());
+        debug!("New {:?} selection: {:?}", ty, mime_types);
+        if let Some(xwm) = self.xwm.as_mut()
+            && let Err(e) = xwm.new_selection(ty, mime_types.clone())
+        {
+            warn!("Failed to pass {:?} selection to X11: {}", ty, e);
+        }
+        if matches!(ty, SelectionTarget::Clipboard) {
+            clipboard::selection_changed(self, &seat, mime_types);
+        }
+    }
+
+    fn send_selection(
+        &mut self,
+        ty: SelectionTarget,
+        mime_type: String,
+        fd: OwnedFd,
+        _seat: Seat<Self>,
+        user_data: &Self::SelectionUserData,
+    ) {
+        match user_data {
+            OfferedSelection::Saved(contents) => clipboard::send(contents, &mime_type, fd),
+            OfferedSelection::Xwayland => {
+                if let Some(xwm) = self.xwm.as_mut()
+                    && let Err(e) = xwm.send_selection(ty, mime_type, fd, self.udev_data.loop_handle.clone())
+                {
+                    warn!("Failed to read X11 {:?} selection: {}", ty, e);
+                }
+            }
+        }
+    }
+}
+
+// Clipboard persistence - saved copies are announced to the shell
+impl ClipboardHandler for DrmCompositorState {
+    fn clipboard(&mut self) -> &mut Clipboard<Self> {
+        &mut self.clipboard
+    }
+
+    fn clipboard_saved(&mut self) {
+        if let Some(server) = self.ipc_server.as_mut() {
+            server.broadcast(&ipc::Event::ClipboardHistoryChanged);
+        }
+    }
+
+    fn saved_clipboard_offered(&mut self, mime_types: Vec<String>) {
+        if let Some(xwm) = self.xwm.as_mut()
+            && let Err(e) = xwm.new_selection(SelectionTarget::Clipboard, Some(mime_types))
+        {
+            warn!("Failed to pass saved clipboard to X11: {}", e);
+        }
+    }
+}
+
+// Xwayland shell handler - pairs X11 windows with their wl_surfaces
+impl XWaylandShellHandler for DrmCompositorState {
+    fn xwayland_shell_state(&mut self) -> &mut XWaylandShellState {
+        &mut self.xwayland_shell_state
+    }
+}
+
+// X11 window manager - X11 apps through Xwayland. Their coordinates are
+// `xwayland_scale` times the logical ones; X11 apps place and size
+// themselves, so requests are granted as asked
+impl XwmHandler for DrmCompositorState {
+    fn xwm_state(&mut self, _xwm: XwmId) -> &mut X11Wm {
+        self.xwm.as_mut().expect("X11 window manager not running")
+    }
+
+    fn new_window(&mut self, _xwm: XwmId, _window: X11Surface) {}
+
+    fn new_override_redirect_window(&mut self, _xwm: XwmId, _window: X11Surface) {}
+
+    fn map_window_request(&mut self, _xwm: XwmId, window: X11Surface) {
+        if let Err(e) = window.set_mapped(true) {
+            warn!("Failed to map X11 window: {}", e);
+            return;
+        }
+        info!("New X11 window: {:?}", window.title());
+        let geometry = from_x11(window.geometry(), self.xwayland_scale);
+        self.space.map_element(Window::new_x11_window(window.clone()), geometry.loc, true);
+        if let Err(e) = window.configure(to_x11(geometry, self.xwayland_scale)) {
+            warn!("Failed to configure X11 window: {}", e);
+        }
+        schedule_render_all(self);
+    }
+
+    fn mapped_override_redirect_window(&mut self, _xwm: XwmId, window: X11Surface) {
+        // Menus and tooltips: placed by the app, never activated
+        let location = from_x11(window.geometry(), self.xwayland_scale).loc;
+        self.space.map_element(Window::new_x11_window(window), location, false);
+        schedule_render_all(self);
+    }
+
+    fn unmapped_window(&mut self, _xwm: XwmId, window: X11Surface) {
+        if let Some(mapped) = find_x11_window(&self.space, &window) {
+            self.space.unmap_elem(&mapped);
+        }
+        if !window.is_override_redirect() && let Err(e) = window.set_mapped(false) {
+            warn!("Failed to unmap X11 window: {}", e);
+        }
+        schedule_render_all(self);
+    }
+
+    fn destroyed_window(&mut self, _xwm: XwmId, _window: X11Surface) {}
+
+    fn configure_request(
+        &mut self,
+        _xwm: XwmId,
+        window: X11Surface,
+        x: Option<i32>,
+        y: Option<i32>,
+        w: Option<u32>,
+        h: Option<u32>,
+        reorder: Option<Reorder>,
+    ) {
+        let mut geometry = window.geometry();
+        if let Some(x) = x {
+            geometry.loc.x = x;
+        }
+        if let Some(y) = y {
+            geometry.loc.y = y;
+        }
+        if let Some(w) = w {
+            geometry.size.w = w as i32;
+        }
+        if let Some(h) = h {
+            geometry.size.h = h as i32;
+        }
+        if let Err(e) = window.configure(geometry) {
+            warn!("Failed to configure X11 window: {}", e);
+        }
+
+        if let Some(mapped) = find_x11_window(&self.space, &window) {
+            self.space.map_element(mapped.clone(), from_x11(geometry, self.xwayland_scale).loc, false);
+            if matches!(reorder, Some(Reorder::Top)) {
+                self.space.raise_element(&mapped, false);
+                if let Some(xwm) = self.xwm.as_mut()
+                    && let Err(e) = xwm.raise_window(&window)
+                {
+                    warn!("Failed to raise X11 window: {}", e);
+                }
+            }
+            schedule_render_all(self);
+        }
+    }
+
+    fn configure_notify(
+        &mut self,
+        _xwm: XwmId,
+        window: X11Surface,
+        geometry: Rectangle<i32, Logical>,
+        _above: Option<u32>,
+    ) {
+        // Override-redirect windows move themselves and only tell us afterwards
+        if let Some(mapped) = find_x11_window(&self.space, &window) {
+            self.space.map_element(mapped, from_x11(geometry, self.xwayland_scale).loc, false);
+            schedule_render_all(self);
+        }
+    }
+
+    fn resize_request(&mut self, _xwm: XwmId, _window: X11Surface, _button: u32, _edges: X11ResizeEdge) {
+        debug!("Interactive resize of X11 windows is not supported yet");
+    }
+
+    fn move_request(&mut self, _xwm: XwmId, _window: X11Surface, _button: u32) {
+        debug!("Interactive move of X11 windows is not supported yet");
+    }
+
+    fn allow_selection_access(&mut self, _xwm: XwmId, _selection: SelectionTarget) -> bool {
+        // Like on Wayland, only the focused app may read the selection
+        let focus = self.seat.get_keyboard().and_then(|keyboard| keyboard.current_focus());
+        let focused_client = focus.as_ref().and_then(|focus| focus.wl_surface()).and_then(|surface| surface.client());
+        match (focused_client, &self.xwayland_client) {
+            (Some(focused), Some(client)) => focused == *client,
+            _ => false,
+        }
+    }
+
+    fn send_selection(&mut self, _xwm: XwmId, selection: SelectionTarget, mime_type: String, fd: OwnedFd) {
+        match selection {
+            SelectionTarget::Clipboard => {
+                if let Some(contents) = self.clipboard.current() {
+                    clipboard::send(contents, &mime_type, fd);
+                } else if let Err(e) = request_data_device_client_selection(&self.seat, mime_type, fd) {
+                    debug!("X11 paste not answered: {:?}", e);
+                }
+            }
+            SelectionTarget::Primary => {
+                if let Err(e) = request_primary_client_selection(&self.seat, mime_type, fd) {
+                    debug!("X11 paste not answered: {:?}", e);
+                }
+            }
+        }
+    }
+
+    fn new_selection(&mut self, _xwm: XwmId, selection: SelectionTarget, mime_types: Vec<String>) {
+        debug!("New X11 {:?} selection: {:?}", selection, mime_types);
+        match selection {
+            SelectionTarget::Clipboard => {
+                self.clipboard.forget_current();
+                set_data_device_selection(&self.display_handle, &self.seat, mime_types, OfferedSelection::Xwayland);
+            }
+            SelectionTarget::Primary => {
+                set_primary_selection(&self.display_handle, &self.seat, mime_types, OfferedSelection::Xwayland);
+            }
+        }
+    }
+
+    fn cleared_selection(&mut self, _xwm: XwmId, selection: SelectionTarget) {
+        // Only if X11 still owns it; a Wayland client may have copied since
+        match selection {
+            SelectionTarget::Clipboard => {
+                if current_data_device_selection_userdata(&self.seat)
+                    .is_some_and(|data| matches!(data.clone(), OfferedSelection::Xwayland))
+                {
+                    clear_data_device_selection(&self.display_handle, &self.seat);
+                }
+            }
+            SelectionTarget::Primary => {
+                if current_primary_selection_userdata(&self.seat)
+                    .is_some_and(|data| matches!(data.clone(), OfferedSelection::Xwayland))
+                {
+                    clear_primary_selection(&self.display_handle, &self.seat);
+                }
+            }
+        }
+    }
 }
 
 // Output handler - handles output (display) management
 impl OutputHandler for DrmCompositorState {}
 
+// Fractional scale handler - tells clients the exact scale of their output
+impl FractionalScaleHandler for DrmCompositorState {
+    fn new_fractional_scale(&mut self, surface: WlSurface) {
+        if let Some(output) = output_for_surface(self, &surface) {
+            let scale = output.current_scale().fractional_scale();
+            debug!("New fractional scale object, preferred scale {}", scale);
+            with_states(&surface, |states| {
+                with_fractional_scale(states, |fractional| fractional.set_preferred_scale(scale));
+            });
+        }
+    }
+}
+
+// Pointer constraints handler - pointer locks and confinement for games, 3D tools and VM viewers
+impl PointerConstraintsHandler for DrmCompositorState {
+    fn new_constraint(&mut self, surface: &WlSurface, pointer: &PointerHandle<Self>) {
+        // Activate right away if the pointer is already over the surface;
+        // otherwise `pointer_moved` does it once the pointer gets there
+        let location = self.pointer_location;
+        if let Some((under, surface_loc)) = input::surface_under(&self.space, location)
+            && under == *surface
+        {
+            with_pointer_constraint(surface, pointer, |constraint| {
+                let Some(constraint) = constraint else { return };
+                let point = (location - surface_loc).to_i32_round();
+                if constraint.region().is_none_or(|region| region.contains(point)) {
+                    debug!("🔒 Activating pointer constraint");
+                    constraint.activate();
+                }
+            });
+        }
+    }
+
+    fn cursor_position_hint(
+        &mut self,
+        surface: &WlSurface,
+        pointer: &PointerHandle<Self>,
+        location: Point<f64, Logical>,
+    ) {
+        // Where the client drew its own cursor while locked; put ours there on unlock
+        let active = with_pointer_constraint(surface, pointer, |constraint| {
+            constraint.is_some_and(|constraint| constraint.is_active())
+        });
+        if !active {
+            return;
+        }
+        let origin = self.space.elements().find_map(|window| {
+            let toplevel = window.toplevel()?;
+            (toplevel.wl_surface() == surface)
+                .then(|| self.space.element_location(window))
+                .flatten()
+                .map(|loc| loc - window.geometry().loc)
+        });
+        if let Some(origin) = origin {
+            self.pointer_location = clamp_to_outputs(&self.space, origin.to_f64() + location);
+            pointer.set_location(self.pointer_location);
+            schedule_render_all(self);
+        }
+    }
+}
+
+// IPC handler - requests from the shell and scripts
+impl IpcHandler for DrmCompositorState {
+    fn ipc_server(&mut self) -> &mut IpcServer<Self> {
+        self.ipc_server.as_mut().expect("IPC requests only arrive while the server is running")
+    }
+
+    fn handle_ipc_request(&mut self, request: ipc::Request) -> ipc::Response {
+        match request {
+            ipc::Request::Subscribe => ipc::Response::Ok,
+            ipc::Request::KeyboardLayouts => {
+                let (layouts, active) = keyboard_layouts(self);
+                ipc::Response::KeyboardLayouts { layouts, active }
+            }
+            ipc::Request::SwitchLayout { index } => {
+                let count = keyboard_layouts(self).0.len();
+                if index.is_some_and(|index| index >= count) {
+                    return ipc::Response::Error {
+                        message: format!("there are only {} layouts", count),
+                    };
+                }
+                switch_keyboard_layout(self, index);
+                ipc::Response::Ok
+            }
+            ipc::Request::ClipboardHistory => ipc::Response::ClipboardHistory {
+                entries: self
+                    .clipboard
+                    .history()
+                    .map(|contents| ipc::ClipboardEntry {
+                        mime_types: contents.mime_types(),
+                        size: contents.size(),
+                        preview: contents.text_preview(ipc::CLIPBOARD_PREVIEW_CHARS),
+                    })
+                    .collect(),
+            },
+            ipc::Request::SetClipboard { index } => {
+                let seat = self.seat.clone();
+                if clipboard::restore(self, &seat, index) {
+                    ipc::Response::Ok
+                } else {
+                    ipc::Response::Error {
+                        message: format!("no clipboard history entry {}", index),
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Tablet seat handler - clients set a cursor per tablet tool
+impl TabletSeatHandler for DrmCompositorState {
+    fn tablet_tool_image(&mut self, tool: &TabletToolDescriptor, image: CursorImageStatus) {
+        if let Some(cursor) = self.tablet_tools.get_mut(tool) {
+            cursor.image = image;
+            schedule_render_all(self);
+        }
+    }
+}
+
 // Use Smithay's delegate macros to wire up the protocol handlers
 delegate_compositor!(DrmCompositorState);
 delegate_xdg_shell!(DrmCompositorState);
+delegate_layer_shell!(DrmCompositorState);
 delegate_shm!(DrmCompositorState);
 delegate_seat!(DrmCompositorState);
 delegate_data_device!(DrmCompositorState);
+delegate_primary_selection!(DrmCompositorState);
+delegate_data_control!(DrmCompositorState);
+delegate_ext_data_control!(DrmCompositorState);
 delegate_output!(DrmCompositorState);
+delegate_fractional_scale!(DrmCompositorState);
+delegate_viewporter!(DrmCompositorState);
+delegate_presentation!(DrmCompositorState);
+delegate_relative_pointer!(DrmCompositorState);
+delegate_pointer_constraints!(DrmCompositorState);
+delegate_pointer_gestures!(DrmCompositorState);
+delegate_tablet_manager!(DrmCompositorState);
+delegate_cursor_shape!(DrmCompositorState);
+delegate_output_management!(DrmCompositorState);
+delegate_xwayland_shell!(DrmCompositorState);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scale_for(size_mm: (u32, u32), mode: (i32, i32)) -> f64 {
+        default_scale(size_mm, smithay::utils::Size::from(mode))
+    }
+
+    #[test]
+    fn default_scale_snaps_to_quarter_steps() {
+        // 13.3" 1440p laptop panel, ~221 DPI against a 135 DPI target
+        assert_eq!(scale_for((294, 165), (2560, 1440)), 1.75);
+        // 27" 4K desktop monitor, ~163 DPI against a 110 DPI target
+        assert_eq!(scale_for((597, 336), (3840, 2160)), 1.5);
+
+        for (size_mm, mode) in [((294, 165), (2560, 1440)), ((344, 194), (1920, 1080)), ((700, 390), (5120, 2880))] {
+            let scale = scale_for(size_mm, mode);
+            assert_eq!((scale * 4.0).fract(), 0.0, "{} is not a multiple of 0.25", scale);
+        }
+    }
+
+    #[test]
+    fn default_scale_keeps_minimum_logical_size() {
+        // ~217 DPI would round to 1.5, leaving only 853x533 logical pixels
+        let scale = scale_for((150, 94), (1280, 800));
+        assert_eq!(scale, 1.25);
+        assert!(1280.0 / scale >= 1024.0 && 800.0 / scale >= 600.0);
+    }
+
+    #[test]
+    fn default_scale_ignores_missing_physical_size() {
+        assert_eq!(scale_for((0, 0), (3840, 2160)), 1.0);
+        assert_eq!(scale_for((16, 9), (3840, 2160)), 1.0);
+    }
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rectangle<i32, Logical> {
+        Rectangle::new((x, y).into(), (w, h).into())
+    }
+
+    #[test]
+    fn clamp_to_areas_table() {
+        // A 1920x1080 output left of a taller 1440x2560 portrait one, which
+        // leaves a gap below the first; and a layout with negative origins
+        let side_by_side = [rect(0, 0, 1920, 1080), rect(1920, 0, 1440, 2560)];
+        let negative = [rect(-2560, -400, 2560, 1440), rect(0, 0, 1920, 1080)];
+        // (outputs, pointer position, clamped position)
+        type Case<'a> = (&'a [Rectangle<i32, Logical>], (f64, f64), (f64, f64));
+        let cases: &[Case] = &[
+            // Inside an output: unchanged
+            (&side_by_side, (100.5, 200.25), (100.5, 200.25)),
+            (&side_by_side, (2000.0, 2000.0), (2000.0, 2000.0)),
+            // In the gap below the short output: onto its bottom edge...
+            (&side_by_side, (100.0, 1500.0), (100.0, 1079.0)),
+            // ...or the tall output's left edge, whichever is nearer
+            (&side_by_side, (1800.0, 2000.0), (1920.0, 2000.0)),
+            // Past the outer edges of the layout
+            (&side_by_side, (-50.0, -50.0), (0.0, 0.0)),
+            (&side_by_side, (4000.0, 3000.0), (3359.0, 2559.0)),
+            // Negative origins
+            (&negative, (-1000.0, -300.0), (-1000.0, -300.0)),
+            (&negative, (-3000.0, -500.0), (-2560.0, -400.0)),
+            (&negative, (500.0, -200.0), (500.0, 0.0)),
+            (&negative, (-1000.0, 1300.0), (-1000.0, 1039.0)),
+            (&negative, (1000.0, 1200.0), (1000.0, 1079.0)),
+        ];
+
+        for (areas, position, expected) in cases {
+            let clamped = clamp_to_areas(areas, Point::from(*position));
+            assert_eq!((clamped.x, clamped.y), *expected, "clamping {:?}", position);
+        }
+    }
+
+    #[test]
+    fn clamp_to_region_table() {
+        use smithay::wayland::compositor::RectangleKind::{Add, Subtract};
+
+        let size = Size::from((800, 600));
+        // Two buttons with a gap between them, and a region reaching past
+        // the surface's top-left corner
+        let buttons = RegionAttributes {
+            rects: vec![(Add, rect(0, 0, 100, 50)), (Add, rect(300, 0, 100, 50))],
+        };
+        let overhanging = RegionAttributes {
+            rects: vec![(Add, rect(-100, -100, 300, 300))],
+        };
+        let holed = RegionAttributes {
+            rects: vec![(Add, rect(0, 0, 400, 400)), (Subtract, rect(0, 0, 100, 100))],
+        };
+        // (region, pointer position, clamped position)
+        type Case<'a> = (Option<&'a RegionAttributes>, (f64, f64), Option<(f64, f64)>);
+        let cases: &[Case] = &[
+            // The whole surface without a region
+            (None, (400.0, 300.0), Some((400.0, 300.0))),
+            (None, (-20.0, 900.0), Some((0.0, 599.0))),
+            // The gap between the buttons goes to the nearer one
+            (Some(&buttons), (150.0, 20.0), Some((99.0, 20.0))),
+            (Some(&buttons), (260.0, 20.0), Some((300.0, 20.0))),
+            (Some(&buttons), (350.0, 400.0), Some((350.0, 49.0))),
+            // Negative coordinates end up on the surface, not the region's
+            // overhanging part
+            (Some(&overhanging), (-50.0, -50.0), Some((0.0, 0.0))),
+            (Some(&overhanging), (-50.0, 120.0), Some((0.0, 120.0))),
+            (Some(&overhanging), (250.0, 250.0), Some((199.0, 199.0))),
+            // Points clamped into a subtracted rectangle are rejected
+            (Some(&holed), (-10.0, 200.0), Some((0.0, 200.0))),
+            (Some(&holed), (-10.0, -10.0), None),
+        ];
+
+        for (region, position, expected) in cases {
+            let clamped = clamp_to_region(size, *region, Point::from(*position));
+            assert_eq!(clamped.map(|p| (p.x, p.y)), *expected, "clamping {:?}", position);
+        }
+    }
+
+    #[test]
+    fn clamp_to_region_outside_the_surface() {
+        let region = RegionAttributes {
+            rects: vec![(smithay::wayland::compositor::RectangleKind::Add, rect(1000, 1000, 10, 10))],
+        };
+        assert_eq!(clamp_to_region(Size::from((800, 600)), Some(&region), Point::from((5.0, 5.0))), None);
+        assert_eq!(clamp_to_region(Size::from((0, 0)), None, Point::from((5.0, 5.0))), None);
+    }
+
+    #[test]
+    fn clamp_to_areas_without_outputs() {
+        let position = Point::from((12.5, -3.0));
+        assert_eq!(clamp_to_areas(&[], position), position);
+        assert_eq!(clamp_to_areas(&[rect(0, 0, 0, 0)], position), position);
+    }
+}
@@ -0,0 +1,224 @@
+// EDID parsing for connected monitors
+//
+// Reads the EDID blob property of a DRM connector and extracts the make,
+// model and serial number. These give us an identity for a monitor that
+// survives reboots and moving it to another port, which the connector name
+// (e.g. "DP-2") does not.
+
+use drm::control::{connector, property, Device as ControlDevice};
+use smithay::output::Output;
+use tracing::{debug, warn};
+
+const EDID_HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const EDID_BLOCK_SIZE: usize = 128;
+
+// Display descriptor tags (EDID 1.4, section 3.10.3)
+const DESCRIPTOR_SERIAL: u8 = 0xff;
+const DESCRIPTOR_NAME: u8 = 0xfc;
+
+/// Monitor information from the EDID base block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdidInfo {
+    /// Manufacturer name, or the 3-letter PNP ID if we don't know the vendor
+    pub make: String,
+    /// Monitor name descriptor, or the product code if there is none
+    pub model: String,
+    /// Serial number descriptor, or the numeric serial; empty if neither is set
+    pub serial: String,
+    /// 3-letter PNP manufacturer ID as stored in the EDID
+    pub pnp_id: String,
+    /// Manufacturer's product code
+    pub product_code: u16,
+}
+
+impl EdidInfo {
+    /// Read and parse the EDID of a connector
+    pub fn for_connector(device: &impl ControlDevice, connector: connector::Handle) -> Option<Self> {
+        let blob = edid_blob(device, connector)?;
+        let info = Self::parse(&blob);
+        if info.is_none() {
+            warn!("Connector {:?} has an invalid EDID ({} bytes)", connector, blob.len());
+        }
+        info
+    }
+
+    /// Parse an EDID base block
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let block = data.get(..EDID_BLOCK_SIZE)?;
+        if block[..8] != EDID_HEADER {
+            return None;
+        }
+        if block.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            debug!("EDID checksum mismatch, parsing anyway");
+        }
+
+        let vendor = u16::from_be_bytes([block[8], block[9]]);
+        let pnp_id: String = [(vendor >> 10) & 0x1f, (vendor >> 5) & 0x1f, vendor & 0x1f]
+            .iter()
+            .map(|&c| (b'A' - 1 + c as u8) as char)
+            .collect();
+        let product_code = u16::from_le_bytes([block[10], block[11]]);
+        let serial_number = u32::from_le_bytes([block[12], block[13], block[14], block[15]]);
+
+        let mut name = None;
+        let mut serial = None;
+        for descriptor in block[54..126].chunks_exact(18) {
+            // Display descriptors start with a zero pixel clock
+            if descriptor[0] != 0 || descriptor[1] != 0 {
+                continue;
+            }
+            match descriptor[3] {
+                DESCRIPTOR_NAME => name = descriptor_text(descriptor),
+                DESCRIPTOR_SERIAL => serial = descriptor_text(descriptor),
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            make: vendor_name(&pnp_id).map(String::from).unwrap_or_else(|| pnp_id.clone()),
+            model: name.unwrap_or_else(|| format!("0x{:04X}", product_code)),
+            serial: serial.unwrap_or_else(|| match serial_number {
+                0 => String::new(),
+                n => n.to_string(),
+            }),
+            pnp_id,
+            product_code,
+        })
+    }
+
+    /// Stable identifier for this monitor, independent of the port it's plugged into.
+    ///
+    /// Built from the raw EDID fields rather than the display names, so it
+    /// doesn't change when we learn a new vendor name. Two identical monitors
+    /// without serial numbers will share an identifier.
+    pub fn identifier(&self) -> String {
+        format!("{}-{:04X}-{}", self.pnp_id, self.product_code, self.serial)
+            .trim_end_matches('-')
+            .to_string()
+    }
+}
+
+/// Stable identity of a monitor, stored in its `Output`'s user data
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OutputIdentity(pub String);
+
+impl OutputIdentity {
+    /// Identity of `output`, falling back to the connector name for outputs
+    /// without a usable EDID (virtual GPUs, some cheap panels)
+    pub fn of(output: &Output) -> String {
+        output
+            .user_data()
+            .get::<OutputIdentity>()
+            .map(|identity| identity.0.clone())
+            .unwrap_or_else(|| output.name())
+    }
+}
+
+fn descriptor_text(descriptor: &[u8]) -> Option<String> {
+    let text = &descriptor[5..18];
+    let end = text.iter().position(|&b| b == b'\n').unwrap_or(text.len());
+    let text: String = text[..end]
+        .iter()
+        .filter(|b| b.is_ascii_graphic() || **b == b' ')
+        .map(|&b| b as char)
+        .collect();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn edid_blob(device: &impl ControlDevice, connector: connector::Handle) -> Option<Vec<u8>> {
+    let props = device.get_properties(connector).ok()?;
+    let (ids, values) = props.as_props_and_values();
+
+    ids.iter().zip(values).find_map(|(&id, &value)| {
+        let info = device.get_property(id).ok()?;
+        if info.name().to_str() != Ok("EDID") {
+            return None;
+        }
+        match info.value_type().convert_value(value) {
+            property::Value::Blob(blob) if blob != 0 => device.get_property_blob(blob).ok(),
+            _ => None,
+        }
+    })
+}
+
+/// Full names for the PNP IDs we commonly see on laptops and desktop monitors
+fn vendor_name(pnp_id: &str) -> Option<&'static str> {
+    Some(match pnp_id {
+        "ACR" => "Acer",
+        "AOC" => "AOC",
+        "APP" => "Apple",
+        "AUO" => "AU Optronics",
+        "AUS" => "ASUS",
+        "BNQ" => "BenQ",
+        "BOE" => "BOE",
+        "CMN" => "Chimei Innolux",
+        "DEL" => "Dell",
+        "ENC" => "EIZO",
+        "GBT" => "Gigabyte",
+        "GSM" => "LG Electronics",
+        "HPN" | "HWP" => "HP",
+        "IVM" => "Iiyama",
+        "LEN" => "Lenovo",
+        "LGD" => "LG Display",
+        "MSI" => "MSI",
+        "NEC" => "NEC",
+        "PHL" => "Philips",
+        "SAM" => "Samsung",
+        "SDC" => "Samsung Display",
+        "SHP" => "Sharp",
+        "SNY" => "Sony",
+        "VSC" => "ViewSonic",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// EDID base block for a "DEL" monitor with product code 0xA0C4, a name
+    /// descriptor and a valid checksum
+    fn edid_block(serial: u32) -> Vec<u8> {
+        let mut block = vec![0u8; EDID_BLOCK_SIZE];
+        block[..8].copy_from_slice(&EDID_HEADER);
+        block[8..10].copy_from_slice(&0x10acu16.to_be_bytes());
+        block[10..12].copy_from_slice(&0xa0c4u16.to_le_bytes());
+        block[12..16].copy_from_slice(&serial.to_le_bytes());
+        block[57] = DESCRIPTOR_NAME;
+        block[59..72].copy_from_slice(b"DELL U2720Q\n ");
+        let sum = block.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        block[127] = 0u8.wrapping_sub(sum);
+        block
+    }
+
+    #[test]
+    fn parses_base_block() {
+        let info = EdidInfo::parse(&edid_block(1234)).unwrap();
+        assert_eq!(info.make, "Dell");
+        assert_eq!(info.model, "DELL U2720Q");
+        assert_eq!(info.serial, "1234");
+        assert_eq!(info.identifier(), "DEL-A0C4-1234");
+
+        let info = EdidInfo::parse(&edid_block(0)).unwrap();
+        assert_eq!(info.serial, "");
+        assert_eq!(info.identifier(), "DEL-A0C4");
+    }
+
+    #[test]
+    fn rejects_bad_blocks() {
+        assert_eq!(EdidInfo::parse(&[]), None);
+        assert_eq!(EdidInfo::parse(&edid_block(1)[..100]), None);
+
+        let mut block = edid_block(1);
+        block[0] = 0xff;
+        assert_eq!(EdidInfo::parse(&block), None);
+    }
+
+    #[test]
+    fn parses_despite_bad_checksum() {
+        let mut block = edid_block(1);
+        block[127] = block[127].wrapping_add(1);
+        assert_eq!(EdidInfo::parse(&block).unwrap().model, "DELL U2720Q");
+    }
+}
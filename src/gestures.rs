@@ -0,0 +1,60 @@
+// Touchpad gestures bound to compositor actions
+//
+// Swipes with a bound finger count are handled by the compositor and not
+// forwarded to clients; everything else (other finger counts, pinch, hold)
+// goes to the focused client over pointer-gestures.
+//
+// By default 4-finger horizontal swipes snap the focused window to the left
+// or right half of its output.
+
+use smithay::utils::{Logical, Point};
+
+use crate::config::GesturesConfig;
+
+/// Distance (in pointer units) a swipe must travel to trigger an action
+const SWIPE_THRESHOLD: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GestureAction {
+    SnapLeft,
+    SnapRight,
+}
+
+/// A swipe the compositor has claimed, accumulating its motion until it ends
+#[derive(Debug)]
+pub struct CompositorSwipe {
+    fingers: u32,
+    delta: Point<f64, Logical>,
+}
+
+impl CompositorSwipe {
+    /// Claim a swipe if any action is bound to its finger count
+    pub fn begin(config: &GesturesConfig, fingers: u32) -> Option<Self> {
+        (fingers == config.snap_fingers).then(|| Self {
+            fingers,
+            delta: Point::from((0.0, 0.0)),
+        })
+    }
+
+    pub fn update(&mut self, delta: Point<f64, Logical>) {
+        self.delta += delta;
+    }
+
+    /// Action for the finished swipe, if it went far enough in a bound direction
+    pub fn finish(self, config: &GesturesConfig) -> Option<GestureAction> {
+        let Point { x, y, .. } = self.delta;
+        if x.abs().max(y.abs()) < SWIPE_THRESHOLD {
+            return None;
+        }
+        let horizontal = x.abs() > y.abs();
+
+        if self.fingers == config.snap_fingers && horizontal {
+            return Some(if x > 0.0 {
+                GestureAction::SnapRight
+            } else {
+                GestureAction::SnapLeft
+            });
+        }
+        None
+    }
+}
@@ -0,0 +1,473 @@
+// Pointer, touch and drag-and-drop input shared by the DRM and nested
+// (winit) backends
+//
+// Each backend tracks its own pointer position and turns its input events
+// into calls to these helpers, so hit-testing, focus-on-click and what
+// clients receive behave the same on bare metal and in a window.
+
+use smithay::{
+    backend::input::{
+        Axis, AxisSource, ButtonState, Event, GestureBeginEvent, GestureEndEvent,
+        GesturePinchUpdateEvent as _, GestureSwipeUpdateEvent as _, InputBackend, InputEvent,
+        PointerAxisEvent, PointerButtonEvent, TouchSlot,
+    },
+    desktop::{layer_map_for_output, LayerSurface, Space, Window, WindowSurfaceType},
+    input::{
+        pointer::{
+            AxisFrame, ButtonEvent, GestureHoldBeginEvent, GestureHoldEndEvent, GesturePinchBeginEvent,
+            GesturePinchEndEvent, GesturePinchUpdateEvent, GestureSwipeBeginEvent, GestureSwipeEndEvent,
+            GestureSwipeUpdateEvent, MotionEvent,
+        },
+        touch::{DownEvent, MotionEvent as TouchMotionEvent, UpEvent},
+        Seat, SeatHandler,
+    },
+    reexports::wayland_server::{protocol::wl_surface::WlSurface, Resource},
+    utils::{Logical, Point, SERIAL_COUNTER},
+    wayland::{
+        compositor::{with_states, SurfaceAttributes},
+        seat::WaylandFocus,
+        shell::wlr_layer::{KeyboardInteractivity, Layer},
+    },
+};
+use tracing::debug;
+
+/// Scroll distance of one wheel detent, matching libinput's default
+const WHEEL_STEP: f64 = 15.0;
+
+/// Layers drawn above windows, topmost first
+const UPPER_LAYERS: [Layer; 2] = [Layer::Overlay, Layer::Top];
+/// Layers drawn below windows, topmost first
+const LOWER_LAYERS: [Layer; 2] = [Layer::Bottom, Layer::Background];
+
+/// What a backend's keyboard focuses: a plain wl_surface, or a type that
+/// also gives X11 windows X11's input focus
+pub trait FocusTarget: WaylandFocus + Sized {
+    fn from_surface(surface: WlSurface) -> Self;
+    /// Focus for a clicked window
+    fn from_window(window: &Window) -> Option<Self>;
+}
+
+impl FocusTarget for WlSurface {
+    fn from_surface(surface: WlSurface) -> Self {
+        surface
+    }
+
+    fn from_window(window: &Window) -> Option<Self> {
+        window.wl_surface().map(|surface| surface.into_owned())
+    }
+}
+
+/// Menus, tooltips and other override-redirect windows of X11 apps. They
+/// are placed by the app and never raised, activated or focused by us.
+pub fn is_override_redirect(window: &Window) -> bool {
+    window.x11_surface().is_some_and(|surface| surface.is_override_redirect())
+}
+
+/// Surface under `location` and that surface's origin in global coordinates
+pub fn surface_under(
+    space: &Space<Window>,
+    location: Point<f64, Logical>,
+) -> Option<(WlSurface, Point<f64, Logical>)> {
+    let in_layer = |(layer, layer_loc): (LayerSurface, Point<i32, Logical>)| {
+        layer
+            .surface_under(location - layer_loc.to_f64(), WindowSurfaceType::ALL)
+            .map(|(surface, surface_loc)| (surface, (layer_loc + surface_loc).to_f64()))
+    };
+
+    if let Some(under) = layer_under(space, location, &UPPER_LAYERS).and_then(in_layer) {
+        return Some(under);
+    }
+    if let Some((window, window_loc)) = space.element_under(location) {
+        return window
+            .surface_under(location - window_loc.to_f64(), WindowSurfaceType::ALL)
+            .map(|(surface, surface_loc)| (surface, (window_loc + surface_loc).to_f64()));
+    }
+    layer_under(space, location, &LOWER_LAYERS).and_then(in_layer)
+}
+
+/// Layer surface (bar, dock, wallpaper) in one of `layers` under `location`,
+/// and its origin in global coordinates
+fn layer_under(
+    space: &Space<Window>,
+    location: Point<f64, Logical>,
+    layers: &[Layer],
+) -> Option<(LayerSurface, Point<i32, Logical>)> {
+    let output = space.output_under(location).next()?;
+    let output_loc = space.output_geometry(output)?.loc;
+    let map = layer_map_for_output(output);
+    layers.iter().find_map(|layer| {
+        let surface = map.layer_under(*layer, location - output_loc.to_f64())?;
+        let geometry = map.layer_geometry(surface)?;
+        Some((surface.clone(), output_loc + geometry.loc))
+    })
+}
+
+/// The layer surface that takes all keyboard input while it's shown (a lock
+/// screen or launcher asking for exclusive keyboard interactivity), if any
+pub fn exclusive_layer(space: &Space<Window>) -> Option<WlSurface> {
+    space.outputs().find_map(|output| {
+        let map = layer_map_for_output(output);
+        UPPER_LAYERS.iter().find_map(|layer| {
+            map.layers_on(*layer)
+                .find(|surface| surface.cached_state().keyboard_interactivity == KeyboardInteractivity::Exclusive)
+                .map(|surface| surface.wl_surface().clone())
+        })
+    })
+}
+
+/// Focus-on-click: raise the window under `location`, mark it as the only
+/// activated window, and return the surface that should get keyboard focus.
+/// Clicking the background deactivates everything.
+///
+/// Layer surfaces take focus when clicked if they accept keyboard input; a
+/// click on a bar or dock that doesn't leaves focus and windows alone. An
+/// exclusive layer keeps focus until it goes away. Clicking an X11 menu or
+/// tooltip changes nothing.
+pub fn click_focus<D>(
+    space: &mut Space<Window>,
+    seat: &Seat<D>,
+    location: Point<f64, Logical>,
+) -> Option<D::KeyboardFocus>
+where
+    D: SeatHandler + 'static,
+    D::KeyboardFocus: FocusTarget,
+{
+    let current_focus = || seat.get_keyboard().and_then(|keyboard| keyboard.current_focus());
+    if let Some(surface) = exclusive_layer(space) {
+        return Some(FocusTarget::from_surface(surface));
+    }
+    if let Some((layer, _)) = layer_under(space, location, &UPPER_LAYERS) {
+        return if layer.can_receive_keyboard_focus() {
+            Some(FocusTarget::from_surface(layer.wl_surface().clone()))
+        } else {
+            current_focus()
+        };
+    }
+
+    let clicked = space.element_under(location).map(|(window, _)| window.clone());
+    if clicked.as_ref().is_some_and(is_override_redirect) {
+        return current_focus();
+    }
+
+    if let Some(window) = &clicked {
+        space.raise_element(window, true);
+    }
+    for window in space.elements().filter(|window| !is_override_redirect(window)) {
+        if window.set_activated(Some(window) == clicked.as_ref())
+            && let Some(toplevel) = window.toplevel()
+        {
+            toplevel.send_pending_configure();
+        }
+    }
+
+    match clicked {
+        Some(window) => FocusTarget::from_window(&window),
+        None => layer_under(space, location, &LOWER_LAYERS)
+            .filter(|(layer, _)| layer.can_receive_keyboard_focus())
+            .map(|(layer, _)| FocusTarget::from_surface(layer.wl_surface().clone())),
+    }
+}
+
+/// Move the pointer to `location`, sending enter/leave as the surface under it changes
+pub fn pointer_motion<D>(
+    state: &mut D,
+    seat: &Seat<D>,
+    under: Option<(WlSurface, Point<f64, Logical>)>,
+    location: Point<f64, Logical>,
+    time: u32,
+) where
+    D: SeatHandler<PointerFocus = WlSurface> + 'static,
+{
+    let Some(pointer) = seat.get_pointer() else {
+        return;
+    };
+    pointer.motion(
+        state,
+        under,
+        &MotionEvent {
+            location,
+            serial: SERIAL_COUNTER.next_serial(),
+            time,
+        },
+    );
+    pointer.frame(state);
+}
+
+/// Give keyboard focus to `focus` (or nothing), e.g. the result of `click_focus`
+pub fn set_keyboard_focus<D>(state: &mut D, seat: &Seat<D>, focus: Option<D::KeyboardFocus>)
+where
+    D: SeatHandler + 'static,
+    D::KeyboardFocus: FocusTarget,
+{
+    if let Some(keyboard) = seat.get_keyboard() {
+        debug!(
+            "Keyboard focus -> {:?}",
+            focus.as_ref().and_then(|focus| focus.wl_surface()).map(|surface| surface.id())
+        );
+        keyboard.set_focus(state, focus, SERIAL_COUNTER.next_serial());
+    }
+}
+
+/// Forward a button press or release to the surface under the pointer
+pub fn pointer_button<D, B, E>(state: &mut D, seat: &Seat<D>, event: &E)
+where
+    D: SeatHandler + 'static,
+    B: InputBackend,
+    E: PointerButtonEvent<B>,
+{
+    let Some(pointer) = seat.get_pointer() else {
+        return;
+    };
+    pointer.button(
+        state,
+        &ButtonEvent {
+            button: event.button_code(),
+            state: event.state(),
+            serial: SERIAL_COUNTER.next_serial(),
+            time: event.time_msec(),
+        },
+    );
+    pointer.frame(state);
+}
+
+/// Whether a button event should move focus (a press while nothing holds a grab)
+pub fn button_moves_focus<D, B, E>(seat: &Seat<D>, event: &E) -> bool
+where
+    D: SeatHandler + 'static,
+    B: InputBackend,
+    E: PointerButtonEvent<B>,
+{
+    event.state() == ButtonState::Pressed
+        && seat.get_pointer().is_some_and(|pointer| !pointer.is_grabbed())
+}
+
+/// Forward a scroll event with continuous, discrete and high-resolution (v120) values
+pub fn pointer_axis<D, B, E>(state: &mut D, seat: &Seat<D>, event: &E)
+where
+    D: SeatHandler + 'static,
+    B: InputBackend,
+    E: PointerAxisEvent<B>,
+{
+    let Some(pointer) = seat.get_pointer() else {
+        return;
+    };
+
+    let mut frame = AxisFrame::new(event.time_msec()).source(event.source());
+    for axis in [Axis::Horizontal, Axis::Vertical] {
+        let v120 = event.amount_v120(axis);
+        // Wheels only report v120; derive a continuous value clients can use too
+        let amount = event
+            .amount(axis)
+            .unwrap_or_else(|| v120.unwrap_or(0.0) * WHEEL_STEP / 120.0);
+
+        if amount != 0.0 {
+            frame = frame
+                .relative_direction(axis, event.relative_direction(axis))
+                .value(axis, amount);
+            if let Some(v120) = v120 {
+                frame = frame.v120(axis, v120 as i32);
+            }
+        } else if event.source() == AxisSource::Finger && event.amount(axis) == Some(0.0) {
+            // Fingers lifted: lets clients start kinetic scrolling
+            frame = frame.stop(axis);
+        }
+    }
+
+    pointer.axis(state, frame);
+    pointer.frame(state);
+}
+
+/// Forward a touchpad swipe, pinch or hold event to the client under the
+/// pointer over pointer-gestures. Other events are ignored.
+pub fn forward_gesture<D, B>(state: &mut D, seat: &Seat<D>, event: &InputEvent<B>)
+where
+    D: SeatHandler + 'static,
+    B: InputBackend,
+{
+    let Some(pointer) = seat.get_pointer() else {
+        return;
+    };
+
+    match event {
+        InputEvent::GestureSwipeBegin { event } => pointer.gesture_swipe_begin(
+            state,
+            &GestureSwipeBeginEvent {
+                serial: SERIAL_COUNTER.next_serial(),
+                time: event.time_msec(),
+                fingers: event.fingers(),
+            },
+        ),
+        InputEvent::GestureSwipeUpdate { event } => pointer.gesture_swipe_update(
+            state,
+            &GestureSwipeUpdateEvent {
+                time: event.time_msec(),
+                delta: event.delta(),
+            },
+        ),
+        InputEvent::GestureSwipeEnd { event } => pointer.gesture_swipe_end(
+            state,
+            &GestureSwipeEndEvent {
+                serial: SERIAL_COUNTER.next_serial(),
+                time: event.time_msec(),
+                cancelled: event.cancelled(),
+            },
+        ),
+        InputEvent::GesturePinchBegin { event } => pointer.gesture_pinch_begin(
+            state,
+            &GesturePinchBeginEvent {
+                serial: SERIAL_COUNTER.next_serial(),
+                time: event.time_msec(),
+                fingers: event.fingers(),
+            },
+        ),
+        InputEvent::GesturePinchUpdate { event } => pointer.gesture_pinch_update(
+            state,
+            &GesturePinchUpdateEvent {
+                time: event.time_msec(),
+                delta: event.delta(),
+                scale: event.scale(),
+                rotation: event.rotation(),
+            },
+        ),
+        InputEvent::GesturePinchEnd { event } => pointer.gesture_pinch_end(
+            state,
+            &GesturePinchEndEvent {
+                serial: SERIAL_COUNTER.next_serial(),
+                time: event.time_msec(),
+                cancelled: event.cancelled(),
+            },
+        ),
+        InputEvent::GestureHoldBegin { event } => pointer.gesture_hold_begin(
+            state,
+            &GestureHoldBeginEvent {
+                serial: SERIAL_COUNTER.next_serial(),
+                time: event.time_msec(),
+                fingers: event.fingers(),
+            },
+        ),
+        InputEvent::GestureHoldEnd { event } => pointer.gesture_hold_end(
+            state,
+            &GestureHoldEndEvent {
+                serial: SERIAL_COUNTER.next_serial(),
+                time: event.time_msec(),
+                cancelled: event.cancelled(),
+            },
+        ),
+        _ => {}
+    }
+}
+
+/// Start a touch point on the surface under it
+pub fn touch_down<D>(
+    state: &mut D,
+    seat: &Seat<D>,
+    under: Option<(WlSurface, Point<f64, Logical>)>,
+    slot: TouchSlot,
+    location: Point<f64, Logical>,
+    time: u32,
+) where
+    D: SeatHandler<TouchFocus = WlSurface> + 'static,
+{
+    if let Some(touch) = seat.get_touch() {
+        touch.down(
+            state,
+            under,
+            &DownEvent {
+                slot,
+                location,
+                serial: SERIAL_COUNTER.next_serial(),
+                time,
+            },
+        );
+    }
+}
+
+/// Move a touch point; it stays with the surface it went down on
+pub fn touch_motion<D>(
+    state: &mut D,
+    seat: &Seat<D>,
+    under: Option<(WlSurface, Point<f64, Logical>)>,
+    slot: TouchSlot,
+    location: Point<f64, Logical>,
+    time: u32,
+) where
+    D: SeatHandler<TouchFocus = WlSurface> + 'static,
+{
+    if let Some(touch) = seat.get_touch() {
+        touch.motion(state, under, &TouchMotionEvent { slot, location, time });
+    }
+}
+
+/// End a touch point
+pub fn touch_up<D>(state: &mut D, seat: &Seat<D>, slot: TouchSlot, time: u32)
+where
+    D: SeatHandler + 'static,
+{
+    if let Some(touch) = seat.get_touch() {
+        touch.up(
+            state,
+            &UpEvent {
+                slot,
+                serial: SERIAL_COUNTER.next_serial(),
+                time,
+            },
+        );
+    }
+}
+
+/// Mark the end of a set of simultaneous touch events
+pub fn touch_frame<D>(state: &mut D, seat: &Seat<D>)
+where
+    D: SeatHandler + 'static,
+{
+    if let Some(touch) = seat.get_touch() {
+        touch.frame(state);
+    }
+}
+
+/// The touch sequence was taken over (e.g. by the compositor); clients drop it
+pub fn touch_cancel<D>(state: &mut D, seat: &Seat<D>)
+where
+    D: SeatHandler + 'static,
+{
+    if let Some(touch) = seat.get_touch() {
+        touch.cancel(state);
+    }
+}
+
+/// Icon a client attached to its drag-and-drop, drawn under the pointer
+/// until the drop or cancel
+pub struct DndIcon {
+    pub surface: WlSurface,
+    /// Where the icon's origin is relative to the pointer
+    pub offset: Point<i32, Logical>,
+}
+
+impl DndIcon {
+    /// The icon's origin starts at the pointer; the client moves it with
+    /// wl_surface.offset
+    pub fn new(surface: WlSurface) -> Self {
+        Self {
+            surface,
+            offset: Point::from((0, 0)),
+        }
+    }
+
+    /// Follow the icon's wl_surface.offset when it commits
+    pub fn commit(&mut self, surface: &WlSurface) {
+        if *surface != self.surface {
+            return;
+        }
+        let delta = with_states(surface, |states| {
+            states
+                .cached_state
+                .get::<SurfaceAttributes>()
+                .current()
+                .buffer_delta
+                .take()
+        });
+        if let Some(delta) = delta {
+            self.offset += delta;
+        }
+    }
+}
@@ -0,0 +1,365 @@
+// IPC for the desktop shell and scripts
+//
+// A Unix socket at `$XDG_RUNTIME_DIR/nuthatch-<WAYLAND_DISPLAY>.sock`
+// (exported as `NUTHATCH_SOCKET`) speaking newline-delimited JSON. Every
+// request gets exactly one response line. After `{"type": "subscribe"}` the
+// client is also sent events (e.g. keyboard layout changes) as they happen.
+// The clipboard history is listed newest first, with a short text preview of
+// each copy; `set_clipboard` makes an entry the current clipboard again.
+//
+// Example:
+//
+//     $ echo '{"type": "keyboard_layouts"}' | socat - UNIX-CONNECT:$NUTHATCH_SOCKET
+//     {"type":"keyboard_layouts","layouts":["English (US)","German"],"active":0}
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+use smithay::reexports::calloop::{
+    generic::Generic, Interest, LoopHandle, Mode, PostAction, RegistrationToken,
+};
+use tracing::{debug, info, warn};
+
+/// Longest request line we accept, to bound memory per client
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+/// Characters of each clipboard history entry sent as its preview
+pub const CLIPBOARD_PREVIEW_CHARS: usize = 200;
+/// Most unsent output we queue for a client before giving up on it
+const MAX_OUTPUT_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Receive events on this connection from now on
+    Subscribe,
+    KeyboardLayouts,
+    /// Switch to the layout at `index`, or the next one if not given
+    SwitchLayout { index: Option<usize> },
+    ClipboardHistory,
+    /// Make clipboard history entry `index` (0 is the newest) the clipboard
+    SetClipboard { index: usize },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Error { message: String },
+    KeyboardLayouts { layouts: Vec<String>, active: usize },
+    ClipboardHistory { entries: Vec<ClipboardEntry> },
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClipboardEntry {
+    pub mime_types: Vec<String>,
+    /// Bytes over all MIME types
+    pub size: usize,
+    /// Start of the contents as text, if a text type was offered. The full
+    /// copy is only available by pasting it.
+    pub preview: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    KeyboardLayoutChanged { name: String, index: usize },
+    /// A copy was added to the clipboard history
+    ClipboardHistoryChanged,
+}
+
+pub trait IpcHandler: Sized + 'static {
+    fn ipc_server(&mut self) -> &mut IpcServer<Self>;
+    fn handle_ipc_request(&mut self, request: Request) -> Response;
+}
+
+struct IpcClient {
+    /// Write half, sharing the socket (and its non-blocking flag) with the
+    /// read source
+    stream: UnixStream,
+    subscribed: bool,
+    /// Responses and events the socket hasn't taken yet
+    output: Vec<u8>,
+    read_token: RegistrationToken,
+    /// Set while waiting for the socket to become writable
+    write_token: Option<RegistrationToken>,
+}
+
+pub struct IpcServer<D: 'static> {
+    path: PathBuf,
+    loop_handle: LoopHandle<'static, D>,
+    listener_token: Option<RegistrationToken>,
+    clients: HashMap<u64, IpcClient>,
+    next_client_id: u64,
+}
+
+impl<D: IpcHandler> IpcServer<D> {
+    /// Bind the socket for this Wayland display and start accepting clients
+    pub fn start(loop_handle: LoopHandle<'static, D>, wayland_display: &str) -> io::Result<Self> {
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set"))?;
+        let path = runtime_dir.join(format!("nuthatch-{}.sock", wayland_display));
+
+        // A leftover socket from a crashed instance would make bind() fail
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+
+        let listener_token = loop_handle
+            .insert_source(
+                Generic::new(listener, Interest::READ, Mode::Level),
+                |_, listener, state| {
+                    loop {
+                        match listener.accept() {
+                            Ok((stream, _)) => accept_client(state, stream),
+                            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                            Err(err) => {
+                                warn!("Failed to accept IPC client: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                    Ok(PostAction::Continue)
+                },
+            )
+            .map_err(|err| io::Error::other(err.error))?;
+
+        // Safety: called during startup, before any other threads exist
+        unsafe { std::env::set_var("NUTHATCH_SOCKET", &path) };
+        info!("📡 IPC listening on {:?}", path);
+
+        Ok(Self {
+            path,
+            loop_handle,
+            listener_token: Some(listener_token),
+            clients: HashMap::new(),
+            next_client_id: 0,
+        })
+    }
+
+    /// Send an event to every subscribed client
+    pub fn broadcast(&mut self, event: &Event) {
+        let Ok(mut line) = serde_json::to_string(event) else {
+            return;
+        };
+        line.push('\n');
+
+        let subscribers: Vec<u64> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.subscribed)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in subscribers {
+            if !self.send(id, line.as_bytes()) {
+                self.remove_client(id);
+            }
+        }
+    }
+
+    /// Stop listening and remove the socket file
+    pub fn shutdown(&mut self) {
+        if let Some(token) = self.listener_token.take() {
+            self.loop_handle.remove(token);
+        }
+        let ids: Vec<u64> = self.clients.keys().copied().collect();
+        for id in ids {
+            self.remove_client(id);
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+
+    /// Queue `data` for a client and write as much as its socket takes now;
+    /// the rest goes out once the socket is writable again. Returns false if
+    /// the client is gone or too far behind and should be disconnected.
+    fn send(&mut self, id: u64, data: &[u8]) -> bool {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return false;
+        };
+        client.output.extend_from_slice(data);
+        if client.output.len() > MAX_OUTPUT_SIZE {
+            debug!("IPC client {} is not reading its output, disconnecting", id);
+            return false;
+        }
+        if let Err(err) = flush(client) {
+            debug!("IPC client {} write error: {}", id, err);
+            return false;
+        }
+        if client.output.is_empty() || client.write_token.is_some() {
+            return true;
+        }
+
+        let stream = match client.stream.try_clone() {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Failed to watch IPC client {}: {}", id, err);
+                return false;
+            }
+        };
+        let inserted = self.loop_handle.insert_source(
+            Generic::new(stream, Interest::WRITE, Mode::Level),
+            move |_, _, state| {
+                let server = state.ipc_server();
+                let Some(client) = server.clients.get_mut(&id) else {
+                    // Removed along with the client
+                    return Ok(PostAction::Continue);
+                };
+                if let Err(err) = flush(client) {
+                    debug!("IPC client {} write error: {}", id, err);
+                    server.remove_client(id);
+                    return Ok(PostAction::Continue);
+                }
+                if !client.output.is_empty() {
+                    return Ok(PostAction::Continue);
+                }
+                client.write_token = None;
+                Ok(PostAction::Remove)
+            },
+        );
+        match inserted {
+            Ok(token) => {
+                client.write_token = Some(token);
+                true
+            }
+            Err(err) => {
+                warn!("Failed to watch IPC client {}: {}", id, err.error);
+                false
+            }
+        }
+    }
+
+    /// Disconnect a client. Its socket sources are removed once the current
+    /// dispatch is over, since this is often called from one of them.
+    fn remove_client(&mut self, id: u64) {
+        let Some(client) = self.clients.remove(&id) else {
+            return;
+        };
+        debug!("Dropping IPC client {}", id);
+        let tokens = [Some(client.read_token), client.write_token];
+        let loop_handle = self.loop_handle.clone();
+        self.loop_handle.insert_idle(move |_| {
+            for token in tokens.into_iter().flatten() {
+                loop_handle.remove(token);
+            }
+        });
+    }
+}
+
+/// Write queued output until the socket would block
+fn flush(client: &mut IpcClient) -> io::Result<()> {
+    while !client.output.is_empty() {
+        match (&client.stream).write(&client.output) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                client.output.drain(..n);
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+fn accept_client<D: IpcHandler>(state: &mut D, stream: UnixStream) {
+    let server = state.ipc_server();
+    let id = server.next_client_id;
+    server.next_client_id += 1;
+
+    // Both halves share the socket, including its non-blocking flag
+    let write_stream = match stream.set_nonblocking(true).and_then(|_| stream.try_clone()) {
+        Ok(write_stream) => write_stream,
+        Err(err) => {
+            warn!("Failed to set up IPC client: {}", err);
+            return;
+        }
+    };
+
+    let mut buffer = Vec::new();
+    let inserted = server.loop_handle.insert_source(
+        Generic::new(stream, Interest::READ, Mode::Level),
+        move |_, stream, state| {
+            let mut chunk = [0u8; 4096];
+            loop {
+                match (&**stream).read(&mut chunk) {
+                    Ok(0) => {
+                        debug!("IPC client {} disconnected", id);
+                        state.ipc_server().remove_client(id);
+                        return Ok(PostAction::Continue);
+                    }
+                    Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => {
+                        debug!("IPC client {} read error: {}", id, err);
+                        state.ipc_server().remove_client(id);
+                        return Ok(PostAction::Continue);
+                    }
+                }
+            }
+
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                if !handle_line(state, id, &line) {
+                    state.ipc_server().remove_client(id);
+                    return Ok(PostAction::Continue);
+                }
+            }
+            if buffer.len() > MAX_REQUEST_SIZE {
+                warn!("IPC client {} sent an oversized request, disconnecting", id);
+                state.ipc_server().remove_client(id);
+            }
+            Ok(PostAction::Continue)
+        },
+    );
+
+    match inserted {
+        Ok(read_token) => {
+            server.clients.insert(
+                id,
+                IpcClient {
+                    stream: write_stream,
+                    subscribed: false,
+                    output: Vec::new(),
+                    read_token,
+                    write_token: None,
+                },
+            );
+            debug!("IPC client {} connected", id);
+        }
+        Err(err) => warn!("Failed to watch IPC client: {}", err.error),
+    }
+}
+
+/// Handle one request line and queue the response. Returns false if the
+/// client is gone or should be disconnected.
+fn handle_line<D: IpcHandler>(state: &mut D, id: u64, line: &[u8]) -> bool {
+    let response = match serde_json::from_slice::<Request>(line) {
+        Ok(Request::Subscribe) => match state.ipc_server().clients.get_mut(&id) {
+            Some(client) => {
+                client.subscribed = true;
+                Response::Ok
+            }
+            None => return false,
+        },
+        Ok(request) => {
+            debug!("IPC request from client {}: {:?}", id, request);
+            state.handle_ipc_request(request)
+        }
+        Err(err) => Response::Error {
+            message: format!("invalid request: {}", err),
+        },
+    };
+
+    let Ok(mut reply) = serde_json::to_string(&response) else {
+        return true;
+    };
+    reply.push('\n');
+    state.ipc_server().send(id, reply.as_bytes())
+}
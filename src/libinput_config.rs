@@ -0,0 +1,155 @@
+// libinput device settings from the config file
+//
+// Settings are matched by device type (`[input.touchpad]`, `[input.mouse]`)
+// and overridden per device name (`[input.devices."..."]`). Anything not set
+// falls back to the device's libinput default, so removing a line from the
+// config and reloading undoes it.
+
+use smithay::reexports::input::{
+    AccelProfile, ClickMethod, Device, DeviceCapability, DeviceConfigError, ScrollMethod,
+};
+use tracing::{debug, info, warn};
+
+use crate::config::{AccelProfileSetting, ClickMethodSetting, InputConfig, ScrollMethodSetting};
+
+/// Apply the configured settings (or libinput defaults) to a device
+pub fn apply(device: &mut Device, config: &InputConfig) {
+    if !device.has_capability(DeviceCapability::Pointer) {
+        return;
+    }
+
+    let name = device.name().to_string();
+    let is_touchpad = device.config_tap_finger_count() > 0;
+    let type_settings = if is_touchpad { &config.touchpad } else { &config.mouse };
+    let settings = match config.devices.get(&name) {
+        Some(device_settings) => device_settings.or(type_settings),
+        None => type_settings.clone(),
+    };
+    info!(
+        "🖱️  Configuring {} {:?}",
+        if is_touchpad { "touchpad" } else { "mouse" },
+        name
+    );
+
+    if device.config_tap_finger_count() > 0 {
+        let enabled = settings.tap.unwrap_or_else(|| device.config_tap_default_enabled());
+        check(&name, "tap", settings.tap.is_some(), device.config_tap_set_enabled(enabled));
+    }
+    if device.config_scroll_has_natural_scroll() {
+        let enabled = settings
+            .natural_scroll
+            .unwrap_or_else(|| device.config_scroll_default_natural_scroll_enabled());
+        check(
+            &name,
+            "natural_scroll",
+            settings.natural_scroll.is_some(),
+            device.config_scroll_set_natural_scroll_enabled(enabled),
+        );
+    }
+    if device.config_accel_is_available() {
+        let profile = settings
+            .accel_profile
+            .map(|profile| match profile {
+                AccelProfileSetting::Flat => AccelProfile::Flat,
+                AccelProfileSetting::Adaptive => AccelProfile::Adaptive,
+            })
+            .or_else(|| device.config_accel_default_profile());
+        if let Some(profile) = profile {
+            check(
+                &name,
+                "accel_profile",
+                settings.accel_profile.is_some(),
+                device.config_accel_set_profile(profile),
+            );
+        }
+        let speed = settings
+            .accel_speed
+            .unwrap_or_else(|| device.config_accel_default_speed())
+            .clamp(-1.0, 1.0);
+        check(&name, "accel_speed", settings.accel_speed.is_some(), device.config_accel_set_speed(speed));
+    }
+    if device.config_dwt_is_available() {
+        let enabled = settings
+            .disable_while_typing
+            .unwrap_or_else(|| device.config_dwt_default_enabled());
+        check(
+            &name,
+            "disable_while_typing",
+            settings.disable_while_typing.is_some(),
+            device.config_dwt_set_enabled(enabled),
+        );
+    }
+    let click_method = settings
+        .click_method
+        .map(|method| match method {
+            ClickMethodSetting::ButtonAreas => ClickMethod::ButtonAreas,
+            ClickMethodSetting::Clickfinger => ClickMethod::Clickfinger,
+        })
+        .or_else(|| device.config_click_default_method());
+    if let Some(method) = click_method {
+        if device.config_click_methods().contains(&method) {
+            check(
+                &name,
+                "click_method",
+                settings.click_method.is_some(),
+                device.config_click_set_method(method),
+            );
+        } else if settings.click_method.is_some() {
+            warn!("{:?} does not support click method {:?}", name, method);
+        }
+    }
+    let scroll_method = settings
+        .scroll_method
+        .map(|method| match method {
+            ScrollMethodSetting::None => ScrollMethod::NoScroll,
+            ScrollMethodSetting::TwoFinger => ScrollMethod::TwoFinger,
+            ScrollMethodSetting::Edge => ScrollMethod::Edge,
+            ScrollMethodSetting::OnButtonDown => ScrollMethod::OnButtonDown,
+        })
+        .or_else(|| device.config_scroll_default_method());
+    if let Some(method) = scroll_method {
+        // Turning scrolling off is always possible but isn't listed as a method
+        if method == ScrollMethod::NoScroll || device.config_scroll_methods().contains(&method) {
+            check(
+                &name,
+                "scroll_method",
+                settings.scroll_method.is_some(),
+                device.config_scroll_set_method(method),
+            );
+        } else if settings.scroll_method.is_some() {
+            warn!("{:?} does not support scroll method {:?}", name, method);
+        }
+    }
+    if device.config_left_handed_is_available() {
+        let enabled = settings
+            .left_handed
+            .unwrap_or_else(|| device.config_left_handed_default());
+        check(
+            &name,
+            "left_handed",
+            settings.left_handed.is_some(),
+            device.config_left_handed_set(enabled),
+        );
+    }
+    if device.config_middle_emulation_is_available() {
+        let enabled = settings
+            .middle_emulation
+            .unwrap_or_else(|| device.config_middle_emulation_default_enabled());
+        check(
+            &name,
+            "middle_emulation",
+            settings.middle_emulation.is_some(),
+            device.config_middle_emulation_set_enabled(enabled),
+        );
+    }
+}
+
+/// Report failures for settings the user asked for; failing to restore a
+/// default on a device that doesn't have the feature is expected
+fn check(device: &str, setting: &str, configured: bool, result: Result<(), DeviceConfigError>) {
+    match result {
+        Ok(()) => {}
+        Err(err) if configured => warn!("Failed to set {} on {:?}: {:?}", setting, device, err),
+        Err(err) => debug!("Could not reset {} on {:?}: {:?}", setting, device, err),
+    }
+}
@@ -3,10 +3,44 @@ mod winit;
 mod drm;
 mod drm_minimal;
 mod drm_new;
+mod clipboard;
+mod config;
 mod cursor;
+mod edid;
+mod gestures;
+mod input;
+mod ipc;
+mod libinput_config;
+mod output_management;
+mod profiles;
+mod xwayland;
+
+use std::time::Duration;
 
 use tracing_subscriber::fmt;
 
+/// `--test-timeout <seconds>` (or `--test-timeout=<seconds>`): exit automatically
+/// after that long. A safety net for VM and bare-metal TTY testing.
+fn test_timeout() -> Option<Duration> {
+    let args: Vec<String> = std::env::args().collect();
+    let value = args
+        .iter()
+        .position(|arg| arg == "--test-timeout")
+        .and_then(|i| args.get(i + 1).cloned())
+        .or_else(|| {
+            args.iter()
+                .find_map(|arg| arg.strip_prefix("--test-timeout=").map(String::from))
+        })?;
+
+    match value.parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => {
+            tracing::warn!("Ignoring invalid --test-timeout value: {:?}", value);
+            None
+        }
+    }
+}
+
 fn main() {
     // Setup logging
     fmt()
@@ -35,7 +69,7 @@ fn main() {
         if use_full_drm {
             tracing::info!("Starting FULL DRM backend...");
             tracing::info!("⚠️  Note: Full rendering not yet implemented, this tests initialization only");
-            if let Err(err) = drm_new::run_udev() {
+            if let Err(err) = drm_new::run_udev(test_timeout()) {
                 tracing::error!("Full DRM backend failed: {}", err);
                 std::process::exit(1);
             }
@@ -54,7 +88,7 @@ fn main() {
         }
     } else {
         tracing::info!("🪟 Using winit backend (nested mode)");
-        if let Err(err) = winit::init_winit() {
+        if let Err(err) = winit::init_winit(test_timeout()) {
             tracing::error!("Failed to initialize winit backend: {}", err);
             std::process::exit(1);
         }
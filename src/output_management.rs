@@ -0,0 +1,785 @@
+// wlr-output-management-unstable-v1
+//
+// Lets tools like wlr-randr, kanshi and the desktop shell's display settings
+// list every head (connector) with all of its modes, and test or apply a new
+// configuration atomically.
+//
+// The compositor owns the actual output state. It pushes a snapshot of every
+// head with `OutputManagementState::update` whenever something changes, and is
+// asked to validate/apply client configurations through `OutputManagementHandler`.
+
+use std::sync::Mutex;
+
+use smithay::{
+    reexports::{
+        wayland_protocols_wlr::output_management::v1::server::{
+            zwlr_output_configuration_head_v1::{self, ZwlrOutputConfigurationHeadV1},
+            zwlr_output_configuration_v1::{self, ZwlrOutputConfigurationV1},
+            zwlr_output_head_v1::{self, ZwlrOutputHeadV1},
+            zwlr_output_manager_v1::{self, ZwlrOutputManagerV1},
+            zwlr_output_mode_v1::{self, ZwlrOutputModeV1},
+        },
+        wayland_server::{
+            backend::ClientId,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+        },
+    },
+    utils::{Logical, Physical, Point, Size, Transform},
+};
+use tracing::{debug, warn};
+
+const VERSION: u32 = 4;
+
+/// A single mode advertised for a head
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeadMode {
+    pub size: Size<i32, Physical>,
+    /// Refresh rate in mHz
+    pub refresh: i32,
+    pub preferred: bool,
+}
+
+/// Snapshot of a head (connector) as advertised to clients
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputHeadState {
+    pub name: String,
+    pub description: String,
+    pub make: String,
+    pub model: String,
+    pub serial_number: String,
+    /// Physical size in millimetres
+    pub physical_size: (i32, i32),
+    pub modes: Vec<HeadMode>,
+    pub enabled: bool,
+    pub current_mode: Option<HeadMode>,
+    pub position: Point<i32, Logical>,
+    pub transform: Transform,
+    pub scale: f64,
+}
+
+/// Mode requested by a client for a head
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModeSetting {
+    /// One of the modes advertised for the head
+    Mode(HeadMode),
+    /// A custom mode, `refresh` is 0 if the client doesn't care
+    Custom { size: Size<i32, Physical>, refresh: i32 },
+}
+
+impl ModeSetting {
+    /// Index of the advertised mode this setting asks for. We can't generate
+    /// timings, so custom modes must match an existing one; a refresh of 0
+    /// means "any", in which case we take the fastest.
+    pub fn resolve(&self, modes: &[HeadMode]) -> Option<usize> {
+        match self {
+            ModeSetting::Mode(wanted) => modes.iter().position(|mode| mode == wanted),
+            ModeSetting::Custom { size, refresh } => modes
+                .iter()
+                .enumerate()
+                .filter(|(_, mode)| mode.size == *size)
+                .filter(|(_, mode)| *refresh == 0 || (mode.refresh - refresh).abs() <= 500)
+                .max_by_key(|(_, mode)| mode.refresh)
+                .map(|(index, _)| index),
+        }
+    }
+}
+
+/// Requested state of a single head
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeadConfiguration {
+    Disabled,
+    /// Unset fields keep their current value
+    Enabled {
+        mode: Option<ModeSetting>,
+        position: Option<Point<i32, Logical>>,
+        transform: Option<Transform>,
+        scale: Option<f64>,
+        adaptive_sync: Option<bool>,
+    },
+}
+
+/// Complete configuration requested by a client, one entry per head name
+pub type OutputConfiguration = Vec<(String, HeadConfiguration)>;
+
+pub trait OutputManagementHandler {
+    fn output_management_state(&mut self) -> &mut OutputManagementState;
+
+    /// Check whether `config` could be applied, without changing anything
+    fn test_output_configuration(&mut self, config: &OutputConfiguration) -> bool;
+
+    /// Apply `config` atomically; returns false (and changes nothing) on failure
+    fn apply_output_configuration(&mut self, config: OutputConfiguration) -> bool;
+}
+
+/// Why a configuration is refused before it reaches the compositor
+#[derive(Debug, Clone, PartialEq)]
+enum ConfigurationError {
+    /// The heads changed since the client created the configuration
+    Outdated,
+    /// The configuration leaves this head out
+    UnconfiguredHead(String),
+}
+
+/// Check that a configuration created at `serial` is still current and
+/// configures every head
+fn check_configuration(
+    serial: u32,
+    current_serial: u32,
+    heads: &[OutputHeadState],
+    configured: &[String],
+) -> Result<(), ConfigurationError> {
+    if serial != current_serial {
+        return Err(ConfigurationError::Outdated);
+    }
+    match heads.iter().find(|head| !configured.contains(&head.name)) {
+        Some(head) => Err(ConfigurationError::UnconfiguredHead(head.name.clone())),
+        None => Ok(()),
+    }
+}
+
+/// User data of a `zwlr_output_mode_v1`
+#[derive(Debug)]
+pub struct ModeData {
+    head: String,
+    mode: HeadMode,
+}
+
+/// User data of a `zwlr_output_configuration_v1`
+#[derive(Debug)]
+pub struct PendingConfiguration {
+    serial: u32,
+    heads: Vec<(String, Option<ZwlrOutputConfigurationHeadV1>)>,
+    used: bool,
+}
+
+/// User data of a `zwlr_output_configuration_head_v1`
+#[derive(Debug, Default)]
+pub struct PendingHead {
+    name: String,
+    mode: Option<ModeSetting>,
+    position: Option<Point<i32, Logical>>,
+    transform: Option<Transform>,
+    scale: Option<f64>,
+    adaptive_sync: Option<bool>,
+}
+
+struct HeadInstance {
+    head: ZwlrOutputHeadV1,
+    modes: Vec<ZwlrOutputModeV1>,
+    sent: OutputHeadState,
+}
+
+struct ManagerInstance {
+    manager: ZwlrOutputManagerV1,
+    heads: Vec<HeadInstance>,
+    stopped: bool,
+}
+
+pub struct OutputManagementState {
+    display: DisplayHandle,
+    serial: u32,
+    heads: Vec<OutputHeadState>,
+    managers: Vec<ManagerInstance>,
+}
+
+impl OutputManagementState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwlrOutputManagerV1, ()>
+            + Dispatch<ZwlrOutputManagerV1, ()>
+            + Dispatch<ZwlrOutputHeadV1, String>
+            + Dispatch<ZwlrOutputModeV1, ModeData>
+            + Dispatch<ZwlrOutputConfigurationV1, Mutex<PendingConfiguration>>
+            + Dispatch<ZwlrOutputConfigurationHeadV1, Mutex<PendingHead>>
+            + OutputManagementHandler
+            + 'static,
+    {
+        display.create_global::<D, ZwlrOutputManagerV1, _>(VERSION, ());
+
+        Self {
+            display: display.clone(),
+            serial: 0,
+            heads: Vec::new(),
+            managers: Vec::new(),
+        }
+    }
+
+    /// Replace the advertised heads and notify every bound client of the changes
+    pub fn update<D>(&mut self, mut heads: Vec<OutputHeadState>)
+    where
+        D: Dispatch<ZwlrOutputHeadV1, String> + Dispatch<ZwlrOutputModeV1, ModeData> + 'static,
+    {
+        heads.sort_by(|a, b| a.name.cmp(&b.name));
+        if heads == self.heads {
+            return;
+        }
+
+        self.heads = heads;
+        self.serial = self.serial.wrapping_add(1);
+        debug!("Output management: {} heads, serial {}", self.heads.len(), self.serial);
+
+        for instance in self.managers.iter_mut().filter(|m| !m.stopped) {
+            // Heads that went away
+            instance.heads.retain(|head| {
+                if self.heads.iter().any(|h| h.name == head.sent.name) {
+                    return true;
+                }
+                for mode in &head.modes {
+                    mode.finished();
+                }
+                head.head.finished();
+                false
+            });
+
+            for info in &self.heads {
+                match instance.heads.iter_mut().find(|h| h.sent.name == info.name) {
+                    Some(head) if head.sent != *info => {
+                        if head.sent.modes != info.modes {
+                            for mode in head.modes.drain(..) {
+                                mode.finished();
+                            }
+                            head.modes = send_modes::<D>(&self.display, &head.head, info);
+                        }
+                        send_head_state(&head.head, &head.modes, info);
+                        head.sent = info.clone();
+                    }
+                    Some(_) => {}
+                    None => {
+                        if let Some(head) = send_new_head::<D>(&self.display, &instance.manager, info) {
+                            instance.heads.push(head);
+                        }
+                    }
+                }
+            }
+
+            instance.manager.done(self.serial);
+        }
+    }
+}
+
+fn send_new_head<D>(
+    dh: &DisplayHandle,
+    manager: &ZwlrOutputManagerV1,
+    info: &OutputHeadState,
+) -> Option<HeadInstance>
+where
+    D: Dispatch<ZwlrOutputHeadV1, String> + Dispatch<ZwlrOutputModeV1, ModeData> + 'static,
+{
+    let client = manager.client()?;
+    let head = client
+        .create_resource::<ZwlrOutputHeadV1, _, D>(dh, manager.version(), info.name.clone())
+        .map_err(|err| warn!("Failed to create output head: {:?}", err))
+        .ok()?;
+    manager.head(&head);
+
+    head.name(info.name.clone());
+    head.description(info.description.clone());
+    if info.physical_size.0 > 0 && info.physical_size.1 > 0 {
+        head.physical_size(info.physical_size.0, info.physical_size.1);
+    }
+    if head.version() >= zwlr_output_head_v1::EVT_MAKE_SINCE {
+        head.make(info.make.clone());
+        head.model(info.model.clone());
+        head.serial_number(info.serial_number.clone());
+    }
+
+    let modes = send_modes::<D>(dh, &head, info);
+    send_head_state(&head, &modes, info);
+
+    Some(HeadInstance {
+        head,
+        modes,
+        sent: info.clone(),
+    })
+}
+
+fn send_modes<D>(dh: &DisplayHandle, head: &ZwlrOutputHeadV1, info: &OutputHeadState) -> Vec<ZwlrOutputModeV1>
+where
+    D: Dispatch<ZwlrOutputModeV1, ModeData> + 'static,
+{
+    let Some(client) = head.client() else {
+        return Vec::new();
+    };
+
+    info.modes
+        .iter()
+        .filter_map(|mode| {
+            let data = ModeData {
+                head: info.name.clone(),
+                mode: *mode,
+            };
+            let wl_mode = client
+                .create_resource::<ZwlrOutputModeV1, _, D>(dh, head.version(), data)
+                .ok()?;
+            head.mode(&wl_mode);
+            wl_mode.size(mode.size.w, mode.size.h);
+            if mode.refresh > 0 {
+                wl_mode.refresh(mode.refresh);
+            }
+            if mode.preferred {
+                wl_mode.preferred();
+            }
+            Some(wl_mode)
+        })
+        .collect()
+}
+
+fn send_head_state(head: &ZwlrOutputHeadV1, modes: &[ZwlrOutputModeV1], info: &OutputHeadState) {
+    head.enabled(info.enabled as i32);
+    if !info.enabled {
+        return;
+    }
+
+    let current = info
+        .current_mode
+        .and_then(|current| info.modes.iter().position(|mode| *mode == current))
+        .and_then(|index| modes.get(index));
+    if let Some(mode) = current {
+        head.current_mode(mode);
+    }
+    head.position(info.position.x, info.position.y);
+    head.transform(info.transform.into());
+    head.scale(info.scale);
+}
+
+impl<D> GlobalDispatch<ZwlrOutputManagerV1, (), D> for OutputManagementState
+where
+    D: GlobalDispatch<ZwlrOutputManagerV1, ()>
+        + Dispatch<ZwlrOutputManagerV1, ()>
+        + Dispatch<ZwlrOutputHeadV1, String>
+        + Dispatch<ZwlrOutputModeV1, ModeData>
+        + OutputManagementHandler
+        + 'static,
+{
+    fn bind(
+        state: &mut D,
+        handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrOutputManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let manager = data_init.init(resource, ());
+        let output_management = state.output_management_state();
+
+        let heads = output_management
+            .heads
+            .iter()
+            .filter_map(|info| send_new_head::<D>(handle, &manager, info))
+            .collect();
+        manager.done(output_management.serial);
+
+        output_management.managers.push(ManagerInstance {
+            manager,
+            heads,
+            stopped: false,
+        });
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputManagerV1, (), D> for OutputManagementState
+where
+    D: Dispatch<ZwlrOutputManagerV1, ()>
+        + Dispatch<ZwlrOutputConfigurationV1, Mutex<PendingConfiguration>>
+        + OutputManagementHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        resource: &ZwlrOutputManagerV1,
+        request: zwlr_output_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_output_manager_v1::Request::CreateConfiguration { id, serial } => {
+                data_init.init(
+                    id,
+                    Mutex::new(PendingConfiguration {
+                        serial,
+                        heads: Vec::new(),
+                        used: false,
+                    }),
+                );
+            }
+            zwlr_output_manager_v1::Request::Stop => {
+                let managers = &mut state.output_management_state().managers;
+                if let Some(instance) = managers.iter_mut().find(|m| m.manager == *resource) {
+                    instance.stopped = true;
+                    instance.heads.clear();
+                }
+                resource.finished();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: &ZwlrOutputManagerV1, _data: &()) {
+        state
+            .output_management_state()
+            .managers
+            .retain(|m| m.manager != *resource);
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputHeadV1, String, D> for OutputManagementState
+where
+    D: Dispatch<ZwlrOutputHeadV1, String> + OutputManagementHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        resource: &ZwlrOutputHeadV1,
+        request: zwlr_output_head_v1::Request,
+        _data: &String,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_output_head_v1::Request::Release => {
+                for instance in &mut state.output_management_state().managers {
+                    instance.heads.retain(|h| h.head != *resource);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputModeV1, ModeData, D> for OutputManagementState
+where
+    D: Dispatch<ZwlrOutputModeV1, ModeData> + OutputManagementHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        resource: &ZwlrOutputModeV1,
+        request: zwlr_output_mode_v1::Request,
+        _data: &ModeData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_output_mode_v1::Request::Release => {
+                for instance in &mut state.output_management_state().managers {
+                    for head in &mut instance.heads {
+                        head.modes.retain(|m| m != resource);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputConfigurationV1, Mutex<PendingConfiguration>, D> for OutputManagementState
+where
+    D: Dispatch<ZwlrOutputConfigurationV1, Mutex<PendingConfiguration>>
+        + Dispatch<ZwlrOutputConfigurationHeadV1, Mutex<PendingHead>>
+        + OutputManagementHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        resource: &ZwlrOutputConfigurationV1,
+        request: zwlr_output_configuration_v1::Request,
+        data: &Mutex<PendingConfiguration>,
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        use zwlr_output_configuration_v1::{Error, Request};
+
+        match request {
+            Request::EnableHead { id, head } => {
+                let name = head.data::<String>().cloned().unwrap_or_default();
+                let config_head = data_init.init(
+                    id,
+                    Mutex::new(PendingHead {
+                        name: name.clone(),
+                        ..Default::default()
+                    }),
+                );
+
+                let mut pending = data.lock().unwrap();
+                if pending.heads.iter().any(|(n, _)| *n == name) {
+                    resource.post_error(Error::AlreadyConfiguredHead, "head has already been configured");
+                    return;
+                }
+                pending.heads.push((name, Some(config_head)));
+            }
+            Request::DisableHead { head } => {
+                let name = head.data::<String>().cloned().unwrap_or_default();
+
+                let mut pending = data.lock().unwrap();
+                if pending.heads.iter().any(|(n, _)| *n == name) {
+                    resource.post_error(Error::AlreadyConfiguredHead, "head has already been configured");
+                    return;
+                }
+                pending.heads.push((name, None));
+            }
+            Request::Apply | Request::Test => {
+                let test_only = matches!(request, Request::Test);
+
+                let config = {
+                    let mut pending = data.lock().unwrap();
+                    if pending.used {
+                        resource.post_error(Error::AlreadyUsed, "configuration has already been applied or tested");
+                        return;
+                    }
+                    pending.used = true;
+
+                    let output_management = state.output_management_state();
+                    let configured: Vec<String> = pending.heads.iter().map(|(name, _)| name.clone()).collect();
+                    match check_configuration(
+                        pending.serial,
+                        output_management.serial,
+                        &output_management.heads,
+                        &configured,
+                    ) {
+                        Ok(()) => {}
+                        Err(ConfigurationError::Outdated) => {
+                            debug!("Output configuration is outdated (serial {} != {})", pending.serial, output_management.serial);
+                            resource.cancelled();
+                            return;
+                        }
+                        Err(ConfigurationError::UnconfiguredHead(name)) => {
+                            resource.post_error(Error::UnconfiguredHead, format!("head {} has not been configured", name));
+                            return;
+                        }
+                    }
+
+                    pending
+                        .heads
+                        .iter()
+                        .map(|(name, config_head)| {
+                            let config = match config_head.as_ref().and_then(|h| h.data::<Mutex<PendingHead>>()) {
+                                Some(head) => {
+                                    let head = head.lock().unwrap();
+                                    HeadConfiguration::Enabled {
+                                        mode: head.mode,
+                                        position: head.position,
+                                        transform: head.transform,
+                                        scale: head.scale,
+                                        adaptive_sync: head.adaptive_sync,
+                                    }
+                                }
+                                None => HeadConfiguration::Disabled,
+                            };
+                            (name.clone(), config)
+                        })
+                        .collect::<OutputConfiguration>()
+                };
+
+                let success = if test_only {
+                    state.test_output_configuration(&config)
+                } else {
+                    state.apply_output_configuration(config)
+                };
+
+                if success {
+                    resource.succeeded();
+                } else {
+                    resource.failed();
+                }
+            }
+            Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputConfigurationHeadV1, Mutex<PendingHead>, D> for OutputManagementState
+where
+    D: Dispatch<ZwlrOutputConfigurationHeadV1, Mutex<PendingHead>> + OutputManagementHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        resource: &ZwlrOutputConfigurationHeadV1,
+        request: zwlr_output_configuration_head_v1::Request,
+        data: &Mutex<PendingHead>,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        use zwlr_output_configuration_head_v1::{Error, Request};
+
+        let mut pending = data.lock().unwrap();
+        match request {
+            Request::SetMode { mode } => {
+                if pending.mode.is_some() {
+                    resource.post_error(Error::AlreadySet, "mode has already been set");
+                    return;
+                }
+                match mode.data::<ModeData>() {
+                    Some(mode) if mode.head == pending.name => {
+                        pending.mode = Some(ModeSetting::Mode(mode.mode));
+                    }
+                    _ => resource.post_error(Error::InvalidMode, "mode doesn't belong to this head"),
+                }
+            }
+            Request::SetCustomMode { width, height, refresh } => {
+                if pending.mode.is_some() {
+                    resource.post_error(Error::AlreadySet, "mode has already been set");
+                    return;
+                }
+                if width <= 0 || height <= 0 || refresh < 0 {
+                    resource.post_error(Error::InvalidCustomMode, "invalid custom mode");
+                    return;
+                }
+                pending.mode = Some(ModeSetting::Custom {
+                    size: (width, height).into(),
+                    refresh,
+                });
+            }
+            Request::SetPosition { x, y } => {
+                if pending.position.is_some() {
+                    resource.post_error(Error::AlreadySet, "position has already been set");
+                    return;
+                }
+                pending.position = Some((x, y).into());
+            }
+            Request::SetTransform { transform } => {
+                if pending.transform.is_some() {
+                    resource.post_error(Error::AlreadySet, "transform has already been set");
+                    return;
+                }
+                match transform {
+                    WEnum::Value(transform) => pending.transform = Some(transform.into()),
+                    WEnum::Unknown(_) => resource.post_error(Error::InvalidTransform, "invalid transform"),
+                }
+            }
+            Request::SetScale { scale } => {
+                if pending.scale.is_some() {
+                    resource.post_error(Error::AlreadySet, "scale has already been set");
+                    return;
+                }
+                if !scale.is_finite() || scale <= 0.0 {
+                    resource.post_error(Error::InvalidScale, "scale must be positive");
+                    return;
+                }
+                pending.scale = Some(scale);
+            }
+            Request::SetAdaptiveSync { state } => {
+                if pending.adaptive_sync.is_some() {
+                    resource.post_error(Error::AlreadySet, "adaptive sync has already been set");
+                    return;
+                }
+                match state {
+                    WEnum::Value(zwlr_output_head_v1::AdaptiveSyncState::Enabled) => pending.adaptive_sync = Some(true),
+                    WEnum::Value(zwlr_output_head_v1::AdaptiveSyncState::Disabled) => pending.adaptive_sync = Some(false),
+                    _ => resource.post_error(Error::InvalidAdaptiveSyncState, "invalid adaptive sync state"),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Wire up the wlr-output-management globals and objects for a state type
+macro_rules! delegate_output_management {
+    ($ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_manager_v1::ZwlrOutputManagerV1: ()
+        ] => $crate::output_management::OutputManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_manager_v1::ZwlrOutputManagerV1: ()
+        ] => $crate::output_management::OutputManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_head_v1::ZwlrOutputHeadV1: String
+        ] => $crate::output_management::OutputManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_mode_v1::ZwlrOutputModeV1: $crate::output_management::ModeData
+        ] => $crate::output_management::OutputManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_configuration_v1::ZwlrOutputConfigurationV1: std::sync::Mutex<$crate::output_management::PendingConfiguration>
+        ] => $crate::output_management::OutputManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_configuration_head_v1::ZwlrOutputConfigurationHeadV1: std::sync::Mutex<$crate::output_management::PendingHead>
+        ] => $crate::output_management::OutputManagementState);
+    };
+}
+pub(crate) use delegate_output_management;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(w: i32, h: i32, refresh: i32) -> HeadMode {
+        HeadMode {
+            size: (w, h).into(),
+            refresh,
+            preferred: false,
+        }
+    }
+
+    fn head(name: &str) -> OutputHeadState {
+        OutputHeadState {
+            name: name.to_string(),
+            description: String::new(),
+            make: String::new(),
+            model: String::new(),
+            serial_number: String::new(),
+            physical_size: (0, 0),
+            modes: vec![mode(1920, 1080, 60000)],
+            enabled: true,
+            current_mode: Some(mode(1920, 1080, 60000)),
+            position: (0, 0).into(),
+            transform: Transform::Normal,
+            scale: 1.0,
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn resolves_advertised_modes() {
+        let modes = [mode(1920, 1080, 60000), mode(1920, 1080, 144000), mode(1280, 720, 60000)];
+        assert_eq!(ModeSetting::Mode(mode(1280, 720, 60000)).resolve(&modes), Some(2));
+        assert_eq!(ModeSetting::Mode(mode(1280, 720, 75000)).resolve(&modes), None);
+    }
+
+    #[test]
+    fn resolves_custom_modes() {
+        let modes = [mode(1920, 1080, 60000), mode(1920, 1080, 144000), mode(1280, 720, 59940)];
+        let custom = |w, h, refresh| ModeSetting::Custom {
+            size: (w, h).into(),
+            refresh,
+        };
+
+        // Any refresh picks the fastest mode of that size
+        assert_eq!(custom(1920, 1080, 0).resolve(&modes), Some(1));
+        assert_eq!(custom(1920, 1080, 60000).resolve(&modes), Some(0));
+        // Within half a hertz of an advertised rate
+        assert_eq!(custom(1280, 720, 60000).resolve(&modes), Some(2));
+        assert_eq!(custom(1280, 720, 50000).resolve(&modes), None);
+        assert_eq!(custom(2560, 1440, 0).resolve(&modes), None);
+    }
+
+    #[test]
+    fn stale_serials_are_outdated() {
+        let heads = [head("DP-1"), head("HDMI-A-1")];
+        let all = names(&["HDMI-A-1", "DP-1"]);
+        assert_eq!(check_configuration(3, 3, &heads, &all), Ok(()));
+        assert_eq!(check_configuration(2, 3, &heads, &all), Err(ConfigurationError::Outdated));
+        // A stale configuration is cancelled even if it also misses heads
+        assert_eq!(
+            check_configuration(2, 3, &heads, &names(&["DP-1"])),
+            Err(ConfigurationError::Outdated)
+        );
+    }
+
+    #[test]
+    fn every_head_must_be_configured() {
+        let heads = [head("DP-1"), head("HDMI-A-1")];
+        assert_eq!(
+            check_configuration(1, 1, &heads, &names(&["DP-1"])),
+            Err(ConfigurationError::UnconfiguredHead("HDMI-A-1".to_string()))
+        );
+        assert_eq!(check_configuration(1, 1, &[], &[]), Ok(()));
+    }
+}
@@ -0,0 +1,264 @@
+// Persistent per-monitor layout profiles
+//
+// Remembers the mode, position, scale and transform of every output for each
+// combination of connected monitors, keyed by their EDID identity (see
+// `edid::OutputIdentity`). When the same set of monitors shows up again
+// (docking, undocking, reboot) the saved layout is re-applied, in the spirit
+// of kanshi.
+//
+// Profiles are stored in `$XDG_STATE_HOME/nuthatch/outputs.toml`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use smithay::utils::Transform;
+use tracing::{debug, info, warn};
+
+/// Saved state of a single monitor within a profile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputProfile {
+    /// Stable identity from the EDID, not the connector name
+    pub identity: String,
+    pub enabled: bool,
+    pub width: i32,
+    pub height: i32,
+    /// Refresh rate in mHz
+    pub refresh: i32,
+    pub x: i32,
+    pub y: i32,
+    pub scale: f64,
+    #[serde(with = "transform_name")]
+    pub transform: Transform,
+}
+
+/// Layout for one particular set of connected monitors
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayoutProfile {
+    pub outputs: Vec<OutputProfile>,
+}
+
+impl LayoutProfile {
+    /// Sorted identities of the monitors this profile applies to
+    pub fn key(&self) -> Vec<String> {
+        let mut key: Vec<String> = self.outputs.iter().map(|o| o.identity.clone()).collect();
+        key.sort();
+        key
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ProfilesFile {
+    #[serde(default, rename = "profile")]
+    profiles: Vec<LayoutProfile>,
+}
+
+pub struct LayoutProfiles {
+    path: Option<PathBuf>,
+    profiles: Vec<LayoutProfile>,
+}
+
+impl LayoutProfiles {
+    /// Load saved profiles from the state file, starting empty if there is none
+    pub fn load() -> Self {
+        Self::load_from(state_file_path())
+    }
+
+    fn load_from(path: Option<PathBuf>) -> Self {
+        let profiles = path
+            .as_deref()
+            .and_then(|path| match fs::read_to_string(path) {
+                Ok(contents) => Some((path, contents)),
+                Err(err) => {
+                    debug!("No layout profiles at {:?}: {}", path, err);
+                    None
+                }
+            })
+            .and_then(|(path, contents)| match toml::from_str::<ProfilesFile>(&contents) {
+                Ok(file) => Some(file.profiles),
+                Err(err) => {
+                    warn!("Ignoring invalid layout profiles in {:?}: {}", path, err);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        info!("Loaded {} layout profile(s)", profiles.len());
+        Self { path, profiles }
+    }
+
+    /// Find the profile for exactly this set of monitor identities
+    pub fn find(&self, identities: &[String]) -> Option<&LayoutProfile> {
+        let mut key = identities.to_vec();
+        key.sort();
+        self.profiles.iter().find(|profile| profile.key() == key)
+    }
+
+    /// Store `profile`, replacing any profile for the same set of monitors,
+    /// and write the state file
+    pub fn remember(&mut self, profile: LayoutProfile) {
+        let key = profile.key();
+        match self.profiles.iter_mut().find(|p| p.key() == key) {
+            Some(existing) if *existing == profile => return,
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+
+        if let Err(err) = self.save() {
+            warn!("Failed to save layout profiles: {}", err);
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let contents = toml::to_string_pretty(&ProfilesFile {
+            profiles: self.profiles.clone(),
+        })?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write to a temporary file first so a crash never leaves a truncated file
+        let tmp = path.with_extension("toml.tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)?;
+
+        debug!("Saved {} layout profile(s) to {:?}", self.profiles.len(), path);
+        Ok(())
+    }
+}
+
+fn state_file_path() -> Option<PathBuf> {
+    let state_dir = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(state_dir.join("nuthatch").join("outputs.toml"))
+}
+
+/// Transforms are stored using the same names as wlr-randr and kanshi
+mod transform_name {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use smithay::utils::Transform;
+
+    pub fn serialize<S: Serializer>(transform: &Transform, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match transform {
+            Transform::Normal => "normal",
+            Transform::_90 => "90",
+            Transform::_180 => "180",
+            Transform::_270 => "270",
+            Transform::Flipped => "flipped",
+            Transform::Flipped90 => "flipped-90",
+            Transform::Flipped180 => "flipped-180",
+            Transform::Flipped270 => "flipped-270",
+        })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Transform, D::Error> {
+        Ok(match String::deserialize(deserializer)?.as_str() {
+            "normal" => Transform::Normal,
+            "90" => Transform::_90,
+            "180" => Transform::_180,
+            "270" => Transform::_270,
+            "flipped" => Transform::Flipped,
+            "flipped-90" => Transform::Flipped90,
+            "flipped-180" => Transform::Flipped180,
+            "flipped-270" => Transform::Flipped270,
+            other => return Err(D::Error::custom(format!("unknown transform {:?}", other))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(identity: &str, x: i32) -> OutputProfile {
+        OutputProfile {
+            identity: identity.to_string(),
+            enabled: true,
+            width: 2560,
+            height: 1440,
+            refresh: 59951,
+            x,
+            y: 0,
+            scale: 1.25,
+            transform: Transform::Flipped90,
+        }
+    }
+
+    /// A state file path in a fresh directory of its own
+    fn state_file(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nuthatch-profiles-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("nuthatch").join("outputs.toml")
+    }
+
+    #[test]
+    fn profiles_survive_a_round_trip() {
+        let path = state_file("round-trip");
+        let profile = LayoutProfile {
+            outputs: vec![output("DEL-A0C4-1234", 0), output("BOE-0BCA", 2048)],
+        };
+
+        let mut profiles = LayoutProfiles::load_from(Some(path.clone()));
+        assert!(profiles.profiles.is_empty());
+        profiles.remember(profile.clone());
+
+        let loaded = LayoutProfiles::load_from(Some(path.clone()));
+        assert_eq!(loaded.profiles, vec![profile]);
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn save_replaces_the_file_through_a_temporary() {
+        let path = state_file("atomic-save");
+        let mut profiles = LayoutProfiles::load_from(Some(path.clone()));
+        profiles.remember(LayoutProfile {
+            outputs: vec![output("DEL-A0C4-1234", 0)],
+        });
+        profiles.remember(LayoutProfile {
+            outputs: vec![output("DEL-A0C4-1234", 100)],
+        });
+
+        assert!(path.exists());
+        assert!(!path.with_extension("toml.tmp").exists());
+        assert_eq!(LayoutProfiles::load_from(Some(path.clone())).profiles[0].outputs[0].x, 100);
+
+        // A temporary left behind by a crash doesn't hide the saved profiles
+        fs::write(path.with_extension("toml.tmp"), "garbage").unwrap();
+        assert_eq!(LayoutProfiles::load_from(Some(path.clone())).profiles.len(), 1);
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn profiles_match_the_exact_set_of_monitors() {
+        let mut profiles = LayoutProfiles::load_from(None);
+        profiles.remember(LayoutProfile {
+            outputs: vec![output("BOE-0BCA", 0)],
+        });
+        profiles.remember(LayoutProfile {
+            outputs: vec![output("DEL-A0C4-1234", 0), output("BOE-0BCA", 2560)],
+        });
+
+        let identities = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        // Connector order doesn't matter, the set does
+        let docked = profiles.find(&identities(&["BOE-0BCA", "DEL-A0C4-1234"])).unwrap();
+        assert_eq!(docked.outputs.len(), 2);
+        assert_eq!(profiles.find(&identities(&["BOE-0BCA"])).unwrap().outputs.len(), 1);
+        assert!(profiles.find(&identities(&["DEL-A0C4-1234"])).is_none());
+        assert!(profiles.find(&identities(&["BOE-0BCA", "DEL-A0C4-1234", "GSM-5B7F"])).is_none());
+
+        // Remembering the same set again replaces its profile
+        profiles.remember(LayoutProfile {
+            outputs: vec![output("BOE-0BCA", 500)],
+        });
+        assert_eq!(profiles.profiles.len(), 2);
+        assert_eq!(profiles.find(&identities(&["BOE-0BCA"])).unwrap().outputs[0].x, 500);
+    }
+}
@@ -1,34 +1,53 @@
 use smithay::{
-    delegate_compositor, delegate_data_device, delegate_output, delegate_seat, delegate_shm,
-    delegate_xdg_shell,
+    delegate_compositor, delegate_cursor_shape, delegate_data_control, delegate_data_device,
+    delegate_ext_data_control, delegate_output, delegate_primary_selection, delegate_seat,
+    delegate_shm, delegate_xdg_shell,
     desktop::{Space, Window},
-    input::{SeatHandler, SeatState},
+    backend::renderer::utils::on_commit_buffer_handler,
+    input::{pointer::CursorImageStatus, Seat, SeatHandler, SeatState},
     reexports::{
         calloop::EventLoop,
         wayland_server::{
             backend::{ClientData, ClientId, DisconnectReason},
-            protocol::{wl_seat::WlSeat, wl_surface::WlSurface},
-            Display,
+            protocol::{wl_data_source::WlDataSource, wl_seat::WlSeat, wl_surface::WlSurface},
+            Display, DisplayHandle, Resource,
         },
     },
-    utils::{Clock, Monotonic},
+    utils::{Clock, Logical, Monotonic, Point},
     wayland::{
         buffer::BufferHandler,
-        compositor::{CompositorClientState, CompositorHandler, CompositorState},
+        compositor::{
+            get_parent, is_sync_subsurface, with_states, CompositorClientState, CompositorHandler,
+            CompositorState,
+        },
+        cursor_shape::CursorShapeManagerState,
         output::{OutputHandler, OutputManagerState},
         selection::{
             data_device::{
-                ClientDndGrabHandler, DataDeviceHandler, ServerDndGrabHandler,
+                set_data_device_focus, ClientDndGrabHandler, DataDeviceHandler, ServerDndGrabHandler,
+            },
+            ext_data_control::{
+                DataControlHandler as ExtDataControlHandler, DataControlState as ExtDataControlState,
             },
+            primary_selection::{set_primary_focus, PrimarySelectionHandler, PrimarySelectionState},
+            wlr_data_control::{DataControlHandler, DataControlState},
             SelectionHandler,
         },
-        shell::xdg::{XdgShellHandler, XdgShellState, PopupSurface, PositionerState, ToplevelSurface},
+        shell::xdg::{
+            XdgShellHandler, XdgShellState, PopupSurface, PositionerState, ToplevelSurface,
+            XdgToplevelSurfaceData,
+        },
         shm::{ShmHandler, ShmState},
+        tablet_manager::TabletSeatHandler,
     },
 };
 
+use crate::input::DndIcon;
+
 pub struct NuthatchState {
     pub start_time: std::time::Instant,
+    pub running: bool,
+    pub display_handle: DisplayHandle,
     pub space: Space<Window>,
     pub clock: Clock<Monotonic>,
     pub compositor_state: CompositorState,
@@ -36,7 +55,17 @@ pub struct NuthatchState {
     pub shm_state: ShmState,
     pub output_manager_state: OutputManagerState,
     pub seat_state: SeatState<Self>,
+    pub cursor_shape_manager_state: CursorShapeManagerState,
+    pub seat: Seat<Self>,
+    pub pointer_location: Point<f64, Logical>,
+    /// What the client under the pointer wants the cursor to look like
+    pub cursor_status: CursorImageStatus,
+    /// Icon of the drag-and-drop in progress
+    pub dnd_icon: Option<DndIcon>,
     pub data_device_state: smithay::wayland::selection::data_device::DataDeviceState,
+    pub primary_selection_state: PrimarySelectionState,
+    pub data_control_state: DataControlState,
+    pub ext_data_control_state: ExtDataControlState,
 }
 
 impl NuthatchState {
@@ -54,7 +83,12 @@ impl NuthatchState {
         let shm_state = ShmState::new::<Self>(&dh, vec![]);
         let output_manager_state = OutputManagerState::new_with_xdg_output::<Self>(&dh);
         let mut seat_state = SeatState::new();
+        let cursor_shape_manager_state = CursorShapeManagerState::new::<Self>(&dh);
         let data_device_state = smithay::wayland::selection::data_device::DataDeviceState::new::<Self>(&dh);
+        let primary_selection_state = PrimarySelectionState::new::<Self>(&dh);
+        let data_control_state = DataControlState::new::<Self, _>(&dh, Some(&primary_selection_state), |_| true);
+        let ext_data_control_state =
+            ExtDataControlState::new::<Self, _>(&dh, Some(&primary_selection_state), |_| true);
 
         // Add a seat for input
         let mut seat = seat_state.new_wl_seat(&dh, "seat-0");
@@ -63,6 +97,8 @@ impl NuthatchState {
 
         Self {
             start_time,
+            running: true,
+            display_handle: dh.clone(),
             space: Space::default(),
             clock,
             compositor_state,
@@ -70,7 +106,15 @@ impl NuthatchState {
             shm_state,
             output_manager_state,
             seat_state,
+            cursor_shape_manager_state,
+            seat,
+            pointer_location: Point::from((0.0, 0.0)),
+            cursor_status: CursorImageStatus::default_named(),
+            dnd_icon: None,
             data_device_state,
+            primary_selection_state,
+            data_control_state,
+            ext_data_control_state,
         }
     }
 }
@@ -87,8 +131,41 @@ impl CompositorHandler for NuthatchState {
 
     fn commit(&mut self, surface: &WlSurface) {
         tracing::trace!("Surface committed: {:?}", surface);
-        // Handle surface commits - update window state if needed
-        // Space doesn't have a commit method in newer Smithay
+        on_commit_buffer_handler::<Self>(surface);
+        if let Some(icon) = &mut self.dnd_icon {
+            icon.commit(surface);
+        }
+
+        if !is_sync_subsurface(surface) {
+            let mut root = surface.clone();
+            while let Some(parent) = get_parent(&root) {
+                root = parent;
+            }
+            if let Some(window) = self
+                .space
+                .elements()
+                .find(|w| w.toplevel().is_some_and(|t| *t.wl_surface() == root))
+            {
+                window.on_commit();
+            }
+        }
+
+        // Toplevels need an initial configure before they attach a buffer
+        if let Some(window) = self
+            .space
+            .elements()
+            .find(|w| w.toplevel().is_some_and(|t| t.wl_surface() == surface))
+        {
+            let initial_configure_sent = with_states(surface, |states| {
+                states
+                    .data_map
+                    .get::<XdgToplevelSurfaceData>()
+                    .is_some_and(|data| data.lock().unwrap().initial_configure_sent)
+            });
+            if !initial_configure_sent && let Some(toplevel) = window.toplevel() {
+                toplevel.send_configure();
+            }
+        }
     }
 }
 
@@ -141,13 +218,21 @@ impl SeatHandler for NuthatchState {
 
     fn focus_changed(&mut self, seat: &smithay::input::Seat<Self>, focused: Option<&WlSurface>) {
         tracing::trace!("Focus changed: {:?}", focused);
+        // Only the focused client may paste
+        let client = focused.and_then(|surface| self.display_handle.get_client(surface.id()).ok());
+        set_data_device_focus(&self.display_handle, seat, client.clone());
+        set_primary_focus(&self.display_handle, seat, client);
     }
 
-    fn cursor_image(&mut self, _seat: &smithay::input::Seat<Self>, _image: smithay::input::pointer::CursorImageStatus) {
-        // Handle cursor changes
+    fn cursor_image(&mut self, _seat: &smithay::input::Seat<Self>, image: CursorImageStatus) {
+        tracing::trace!("Cursor image changed: {:?}", image);
+        self.cursor_status = image;
     }
 }
 
+// Cursor shapes can target tablet tools too; the nested backend has no tablets
+impl TabletSeatHandler for NuthatchState {}
+
 // Data device handler
 impl DataDeviceHandler for NuthatchState {
     fn data_device_state(&self) -> &smithay::wayland::selection::data_device::DataDeviceState {
@@ -155,12 +240,38 @@ impl DataDeviceHandler for NuthatchState {
     }
 }
 
+impl PrimarySelectionHandler for NuthatchState {
+    fn primary_selection_state(&self) -> &PrimarySelectionState {
+        &self.primary_selection_state
+    }
+}
+
+impl DataControlHandler for NuthatchState {
+    fn data_control_state(&self) -> &DataControlState {
+        &self.data_control_state
+    }
+}
+
+impl ExtDataControlHandler for NuthatchState {
+    fn data_control_state(&self) -> &ExtDataControlState {
+        &self.ext_data_control_state
+    }
+}
+
 // Selection handlers
 impl SelectionHandler for NuthatchState {
     type SelectionUserData = ();
 }
 
-impl ClientDndGrabHandler for NuthatchState {}
+impl ClientDndGrabHandler for NuthatchState {
+    fn started(&mut self, _source: Option<WlDataSource>, icon: Option<WlSurface>, _seat: Seat<Self>) {
+        self.dnd_icon = icon.map(DndIcon::new);
+    }
+
+    fn dropped(&mut self, _target: Option<WlSurface>, _validated: bool, _seat: Seat<Self>) {
+        self.dnd_icon = None;
+    }
+}
 impl ServerDndGrabHandler for NuthatchState {}
 
 // Buffer handler
@@ -172,6 +283,7 @@ impl BufferHandler for NuthatchState {
 impl OutputHandler for NuthatchState {}
 
 // Client state
+#[derive(Default)]
 pub struct ClientState {
     pub compositor_state: CompositorClientState,
 }
@@ -188,3 +300,7 @@ delegate_shm!(NuthatchState);
 delegate_output!(NuthatchState);
 delegate_seat!(NuthatchState);
 delegate_data_device!(NuthatchState);
+delegate_primary_selection!(NuthatchState);
+delegate_data_control!(NuthatchState);
+delegate_ext_data_control!(NuthatchState);
+delegate_cursor_shape!(NuthatchState);
@@ -1,25 +1,54 @@
 use smithay::{
     backend::{
-        renderer::{damage::OutputDamageTracker, gles::GlesRenderer, Frame, Renderer, Texture},
-        winit::{self, WinitEvent},
+        input::{AbsolutePositionEvent, Event, InputEvent},
+        renderer::{
+            damage::OutputDamageTracker,
+            element::{
+                surface::{render_elements_from_surface_tree, WaylandSurfaceRenderElement},
+                Kind,
+            },
+            gles::GlesRenderer,
+            ImportAll,
+        },
+        winit::{self, WinitEvent, WinitInput},
     },
+    desktop::{
+        space::{space_render_elements, SpaceRenderElements},
+        utils::send_frames_surface_tree,
+    },
+    input::pointer::{CursorIcon, CursorImageStatus},
     output::{Mode, Output, PhysicalProperties, Subpixel},
     reexports::{
-        calloop::EventLoop,
+        calloop::{
+            signals::{Signal, Signals},
+            EventLoop,
+        },
         wayland_server::Display,
     },
     utils::Transform,
+    wayland::socket::ListeningSocketSource,
 };
 
-use crate::state::NuthatchState;
+use crate::cursor;
+use crate::input;
+use crate::state::{ClientState, NuthatchState};
+
+use std::{sync::Arc, time::Duration};
 
-use std::time::Duration;
+smithay::backend::renderer::element::render_elements! {
+    NestedElements<R, E> where R: ImportAll;
+    Surface=WaylandSurfaceRenderElement<R>,
+    Space=SpaceRenderElements<R, E>,
+}
+type NestedRenderElements<R> = NestedElements<R, WaylandSurfaceRenderElement<R>>;
 
-pub fn init_winit() -> Result<(), Box<dyn std::error::Error>> {
+/// Run the nested compositor until the window is closed, SIGINT/SIGTERM
+/// arrives, or `test_timeout` (if any) expires.
+pub fn init_winit(test_timeout: Option<Duration>) -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!("Initializing Nuthatch Compositor with winit backend");
 
     // Create event loop
-    let event_loop: EventLoop<NuthatchState> = EventLoop::try_new()?;
+    let mut event_loop: EventLoop<NuthatchState> = EventLoop::try_new()?;
     
     // Create Wayland display
     let mut display: Display<NuthatchState> = Display::new()?;
@@ -27,6 +56,26 @@ pub fn init_winit() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize compositor state
     let mut state = NuthatchState::new(&mut display, &event_loop);
 
+    // Listen for clients on $XDG_RUNTIME_DIR/wayland-N
+    let socket = ListeningSocketSource::new_auto()?;
+    let socket_name = socket.socket_name().to_string_lossy().into_owned();
+    let mut display_handle = display.handle();
+    let socket_token = event_loop.handle().insert_source(socket, move |client_stream, _, _| {
+        if let Err(e) = display_handle.insert_client(client_stream, Arc::new(ClientState::default())) {
+            tracing::warn!("Failed to add Wayland client: {}", e);
+        }
+    })?;
+    // Safety: we are still single-threaded here
+    unsafe { std::env::set_var("WAYLAND_DISPLAY", &socket_name) };
+    tracing::info!("Listening on Wayland socket: {}", socket_name);
+
+    // Stop cleanly on Ctrl+C / SIGTERM
+    let signals = Signals::new(&[Signal::SIGINT, Signal::SIGTERM])?;
+    event_loop.handle().insert_source(signals, |event, _, state| {
+        tracing::info!("Received {:?} - shutting down", event.signal());
+        state.running = false;
+    })?;
+
     // Initialize winit backend
     let (mut backend, mut winit_evt_loop) = winit::init::<GlesRenderer>()?;
     
@@ -63,10 +112,16 @@ pub fn init_winit() -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!("Compositor ready - clients can connect");
 
     let mut damage_tracker = OutputDamageTracker::from_output(&output);
+    let mut applied_host_cursor = Some(CursorIcon::Default);
 
     // Main event loop
     let mut frame_count = 0u64;
-    loop {
+    while state.running {
+        if test_timeout.is_some_and(|limit| state.start_time.elapsed() >= limit) {
+            tracing::info!("Test timeout reached - exiting");
+            break;
+        }
+
         // Dispatch Wayland events
         display.dispatch_clients(&mut state)?;
 
@@ -85,6 +140,7 @@ pub fn init_winit() -> Result<(), Box<dyn std::error::Error>> {
             }
             WinitEvent::Input(input_event) => {
                 tracing::trace!("Input event: {:?}", input_event);
+                handle_input(&mut state, &output, input_event);
             }
             WinitEvent::Focus(_) => {}
             WinitEvent::Redraw => {
@@ -92,7 +148,7 @@ pub fn init_winit() -> Result<(), Box<dyn std::error::Error>> {
             }
             WinitEvent::CloseRequested => {
                 tracing::info!("Closing compositor");
-                std::process::exit(0);
+                state.running = false;
             }
         });
 
@@ -101,76 +157,136 @@ pub fn init_winit() -> Result<(), Box<dyn std::error::Error>> {
 
         // Render frame if needed
         if needs_redraw {
-            // Get size before binding to avoid borrow checker issues
-            let size = backend.window_size();
-            
             // Log rendering attempt on first few frames
             if frame_count < 5 {
-                tracing::info!("Rendering frame {} at size {:?}", frame_count, size);
+                tracing::info!("Rendering frame {} at size {:?}", frame_count, backend.window_size());
             }
-            
-            // Bind the backend to get renderer and target
-            let mut render_success = false;
-            {
-                match backend.bind() {
-                    Ok((renderer, mut target)) => {
-                        // Render a frame with a dark blue background
-                        match renderer.render(
-                            &mut target,
-                            size.to_logical(1).to_physical(1),
-                            Transform::Normal,
-                        ) {
-                            Ok(mut frame) => {
-                                // Clear the screen with a nice dark blue color
-                                if let Err(e) = frame.clear([0.1, 0.1, 0.3, 1.0].into(), &[]) {
-                                    tracing::warn!("Failed to clear frame: {}", e);
-                                } else if frame_count < 5 {
-                                    tracing::info!("Frame {} cleared successfully", frame_count);
-                                }
-                                
-                                // Finish the frame - this commits the rendering
-                                match frame.finish() {
-                                    Ok(_sync_point) => {
-                                        if frame_count < 5 {
-                                            tracing::info!("Frame {} finished successfully", frame_count);
-                                        }
-                                        render_success = true;
-                                    }
-                                    Err(e) => {
-                                        tracing::warn!("Failed to finish frame: {}", e);
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                tracing::warn!("Failed to start rendering: {}", e);
-                            }
-                        }
+
+            // Themed cursors are drawn by the host; client surfaces and hiding are ours
+            let host_cursor = match &state.cursor_status {
+                CursorImageStatus::Named(icon) => Some(*icon),
+                CursorImageStatus::Hidden | CursorImageStatus::Surface(_) => None,
+            };
+            if host_cursor != applied_host_cursor {
+                backend.window().set_cursor_visible(host_cursor.is_some());
+                if let Some(icon) = host_cursor {
+                    backend.window().set_cursor(icon);
+                }
+                applied_host_cursor = host_cursor;
+            }
+
+            let age = backend.buffer_age().unwrap_or(0);
+            let mut damage = None;
+            match backend.bind() {
+                Ok((renderer, mut framebuffer)) => {
+                    let mut elements: Vec<NestedRenderElements<_>> = Vec::new();
+                    if let CursorImageStatus::Surface(surface) = &state.cursor_status {
+                        let hotspot = cursor::surface_hotspot(surface);
+                        let position = (state.pointer_location.to_i32_round() - hotspot).to_physical(1);
+                        let cursor_elements: Vec<WaylandSurfaceRenderElement<_>> = render_elements_from_surface_tree(
+                            renderer,
+                            surface,
+                            position,
+                            1.0,
+                            1.0,
+                            Kind::Cursor,
+                        );
+                        elements.extend(cursor_elements.into_iter().map(NestedRenderElements::from));
                     }
-                    Err(e) => {
-                        tracing::warn!("Failed to bind backend: {}", e);
+                    if let Some(icon) = &state.dnd_icon {
+                        let position = (state.pointer_location.to_i32_round() + icon.offset).to_physical(1);
+                        let icon_elements: Vec<WaylandSurfaceRenderElement<_>> = render_elements_from_surface_tree(
+                            renderer,
+                            &icon.surface,
+                            position,
+                            1.0,
+                            1.0,
+                            Kind::Unspecified,
+                        );
+                        elements.extend(icon_elements.into_iter().map(NestedRenderElements::from));
                     }
+                    match space_render_elements(renderer, [&state.space], &output, 1.0) {
+                        Ok(space_elements) => elements.extend(space_elements.into_iter().map(NestedRenderElements::from)),
+                        Err(e) => tracing::warn!("Output has no mode, skipping windows: {:?}", e),
+                    }
+
+                    match damage_tracker.render_output(renderer, &mut framebuffer, age, &elements, [0.1, 0.1, 0.3, 1.0]) {
+                        Ok(result) => damage = result.damage.cloned(),
+                        Err(e) => tracing::warn!("Failed to render frame: {:?}", e),
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to bind backend: {}", e);
                 }
-            } // Drop all borrows here
-            
-            // Now submit to actually present to screen with full window damage
-            if render_success {
-                // Create damage rect for the entire window
-                use smithay::utils::{Rectangle, Physical};
-                let damage = Rectangle::from_loc_and_size((0, 0), size);
-                
-                if let Err(e) = backend.submit(Some(&[damage])) {
+            }
+
+            // Present only what changed; without damage (or when rendering
+            // failed) the previous frame stays on screen and nothing is submitted
+            if let Some(damage) = damage {
+                if let Err(e) = backend.submit(Some(&damage)) {
                     tracing::warn!("Failed to submit frame: {}", e);
                 } else if frame_count < 5 {
-                    tracing::info!("Frame {} submitted successfully with damage", frame_count);
+                    tracing::info!("Frame {} submitted", frame_count);
                 }
             }
+
+            send_frame_callbacks(&state, &output);
         }
 
         // Flush clients
         display.flush_clients()?;
 
-        // Target 60fps
-        std::thread::sleep(Duration::from_millis(16));
+        // Target 60fps, handling signals and new clients while we wait
+        event_loop.dispatch(Some(Duration::from_millis(16)), &mut state)?;
         frame_count = frame_count.wrapping_add(1);
     }
+
+    // Unlink the socket, then drop the display to disconnect all clients
+    event_loop.handle().remove(socket_token);
+    drop(display);
+    tracing::info!("Compositor stopped");
+    Ok(())
+}
+
+/// Forward pointer input from the host window to clients, the same way the
+/// DRM backend does for libinput devices
+fn handle_input(state: &mut NuthatchState, output: &Output, event: InputEvent<WinitInput>) {
+    let seat = state.seat.clone();
+    match event {
+        InputEvent::PointerMotionAbsolute { event } => {
+            let Some(geometry) = state.space.output_geometry(output) else {
+                return;
+            };
+            let location = geometry.loc.to_f64() + event.position_transformed(geometry.size);
+            state.pointer_location = location;
+            let under = input::surface_under(&state.space, location);
+            input::pointer_motion(state, &seat, under, location, event.time_msec());
+        }
+        InputEvent::PointerButton { event } => {
+            if input::button_moves_focus(&seat, &event) {
+                let focus = input::click_focus(&mut state.space, &seat, state.pointer_location);
+                input::set_keyboard_focus(state, &seat, focus);
+            }
+            input::pointer_button(state, &seat, &event);
+        }
+        InputEvent::PointerAxis { event } => {
+            input::pointer_axis(state, &seat, &event);
+        }
+        _ => {}
+    }
+}
+
+/// Let clients on the output draw their next frame, including an animated
+/// cursor surface and drag icon
+fn send_frame_callbacks(state: &NuthatchState, output: &Output) {
+    let time = state.clock.now();
+    for window in state.space.elements() {
+        window.send_frame(output, time, Some(Duration::ZERO), |_, _| Some(output.clone()));
+    }
+    if let CursorImageStatus::Surface(surface) = &state.cursor_status {
+        send_frames_surface_tree(surface, output, time, Some(Duration::ZERO), |_, _| Some(output.clone()));
+    }
+    if let Some(icon) = &state.dnd_icon {
+        send_frames_surface_tree(&icon.surface, output, time, Some(Duration::ZERO), |_, _| Some(output.clone()));
+    }
 }
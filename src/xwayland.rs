@@ -0,0 +1,163 @@
+// On-demand Xwayland
+//
+// We bind the X11 display ourselves: the `/tmp/.X<n>-lock` file, the socket
+// at `/tmp/.X11-unix/X<n>` and its abstract twin. $DISPLAY points at them
+// from startup, but Xwayland only runs once the first X11 client connects.
+// It inherits the listening sockets (`-listenfd`) and accepts the waiting
+// client itself, so sessions without X11 apps never start it.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::{
+        fd::{AsRawFd, BorrowedFd, OwnedFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixListener, UnixStream},
+            process::CommandExt,
+        },
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use smithay::reexports::rustix::io::{fcntl_setfd, FdFlags};
+use tracing::debug;
+
+const SOCKET_DIR: &str = "/tmp/.X11-unix";
+/// Highest display number we try before giving up
+const MAX_DISPLAY: u32 = 32;
+
+/// A bound X11 display. The lock file and socket are removed when dropped.
+pub struct X11Sockets {
+    display: u32,
+    lock_path: PathBuf,
+    socket_path: PathBuf,
+    /// The socket in `SOCKET_DIR` and the abstract one with the same name
+    pub listeners: Vec<UnixListener>,
+}
+
+impl X11Sockets {
+    /// Bind the lowest free display number
+    pub fn bind() -> io::Result<Self> {
+        fs::create_dir_all(SOCKET_DIR)?;
+        for number in 0..=MAX_DISPLAY {
+            match Self::bind_display(number) {
+                Ok(sockets) => return Ok(sockets),
+                Err(err) => debug!("X11 display :{} unavailable: {}", number, err),
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::AddrInUse, "no free X11 display number"))
+    }
+
+    pub fn display(&self) -> u32 {
+        self.display
+    }
+
+    fn bind_display(display: u32) -> io::Result<Self> {
+        let lock_path = PathBuf::from(format!("/tmp/.X{}-lock", display));
+        let mut lock = match create_lock(&lock_path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && lock_is_stale(&lock_path) => {
+                debug!("Removing stale X11 lock {:?}", lock_path);
+                fs::remove_file(&lock_path)?;
+                create_lock(&lock_path)?
+            }
+            lock => lock?,
+        };
+
+        // From here on the display is ours and Drop cleans up after us
+        let mut sockets = Self {
+            display,
+            lock_path,
+            socket_path: Path::new(SOCKET_DIR).join(format!("X{}", display)),
+            listeners: Vec::new(),
+        };
+        // X servers write their pid right-aligned in 10 columns
+        writeln!(lock, "{:>10}", std::process::id())?;
+
+        // Left behind by a server that crashed while holding this display
+        let _ = fs::remove_file(&sockets.socket_path);
+        sockets.listeners.push(UnixListener::bind(&sockets.socket_path)?);
+        let abstract_name = SocketAddr::from_abstract_name(sockets.socket_path.as_os_str().as_bytes())?;
+        sockets.listeners.push(UnixListener::bind_addr(&abstract_name)?);
+        Ok(sockets)
+    }
+}
+
+impl Drop for X11Sockets {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket_path);
+        let _ = fs::remove_file(&self.lock_path);
+    }
+}
+
+fn create_lock(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Whether the X server that wrote this lock file is gone
+fn lock_is_stale(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .is_some_and(|pid| !Path::new(&format!("/proc/{}", pid)).exists())
+}
+
+/// A starting Xwayland and our ends of its connections
+pub struct XwaylandProcess {
+    /// Xwayland's Wayland connection, to insert as a client
+    pub wayland_socket: UnixStream,
+    /// The X11 connection the window manager runs on
+    pub wm_socket: UnixStream,
+    /// Readable once Xwayland accepts X11 clients; end of file if it died first
+    pub ready: File,
+}
+
+/// Start Xwayland on `sockets`
+pub fn spawn(sockets: &X11Sockets) -> io::Result<XwaylandProcess> {
+    let (wayland_socket, xwayland_wayland) = UnixStream::pair()?;
+    let (wm_socket, xwayland_wm) = UnixStream::pair()?;
+    let (ready, ready_writer) = io::pipe()?;
+
+    let mut command = Command::new("Xwayland");
+    command
+        .arg(format!(":{}", sockets.display))
+        .arg("-rootless")
+        .args(["-wm", &xwayland_wm.as_raw_fd().to_string()])
+        .args(["-displayfd", &ready_writer.as_raw_fd().to_string()]);
+    for listener in &sockets.listeners {
+        command.args(["-listenfd", &listener.as_raw_fd().to_string()]);
+    }
+    command
+        .env("WAYLAND_SOCKET", xwayland_wayland.as_raw_fd().to_string())
+        .env_remove("DISPLAY")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // Everything we create is close-on-exec; Xwayland needs these fds
+    let inherited: Vec<RawFd> = [xwayland_wayland.as_raw_fd(), xwayland_wm.as_raw_fd(), ready_writer.as_raw_fd()]
+        .into_iter()
+        .chain(sockets.listeners.iter().map(AsRawFd::as_raw_fd))
+        .collect();
+    // Safety: only fcntl runs between fork and exec, on fds that stay open
+    // in the parent until spawn returns
+    unsafe {
+        command.pre_exec(move || {
+            for &fd in &inherited {
+                fcntl_setfd(BorrowedFd::borrow_raw(fd), FdFlags::empty())?;
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    std::thread::spawn(move || child.wait());
+
+    // Xwayland's ends close here, so `ready` sees end of file if it exits
+    Ok(XwaylandProcess {
+        wayland_socket,
+        wm_socket,
+        ready: File::from(OwnedFd::from(ready)),
+    })
+}
@@ -0,0 +1,56 @@
+#!/bin/bash
+# Check the cursor plane choice of the full DRM backend on vkms (the kernel's
+# virtual KMS driver), so no real display is needed:
+#   - vkms with a cursor plane:    the cursor must go on the hardware plane
+#   - vkms without a cursor plane: the cursor must fall back to compositing
+#
+# Run as root from a TTY (libseat needs a seat), ideally in a VM where vkms
+# is the only KMS device. Exits 77 (skipped) if vkms isn't available.
+# Usage: sudo ./test-cursor-plane-vkms.sh
+
+cd "$(dirname "$0")"
+
+echo "Building compositor..."
+cargo build --release || exit 1
+BIN=./target/release/nuthatch-compositor
+
+# /dev/dri/cardN of the loaded vkms device
+vkms_card() {
+    for card in /sys/class/drm/card[0-9]*; do
+        if [ "$(basename "$(readlink -f "$card/device/driver")")" = "vkms" ]; then
+            echo "/dev/dri/$(basename "$card")"
+            return 0
+        fi
+    done
+    return 1
+}
+
+# run_case <vkms enable_cursor> <expected log line>
+run_case() {
+    modprobe -r vkms 2>/dev/null
+    if ! modprobe vkms enable_cursor="$1"; then
+        echo "SKIP: vkms module not available"
+        exit 77
+    fi
+    sleep 1
+    local card
+    card=$(vkms_card) || { echo "FAIL: vkms loaded but no card found"; return 1; }
+
+    local log
+    log=$(mktemp)
+    NUTHATCH_DRM_DEVICE="$card" RUST_LOG=info "$BIN" --drm --drm-full --test-timeout 5 >"$log" 2>&1
+    if grep -q "$2" "$log"; then
+        echo "PASS: enable_cursor=$1 -> \"$2\""
+        rm -f "$log"
+        return 0
+    fi
+    echo "FAIL: enable_cursor=$1, expected \"$2\" (log: $log)"
+    grep "Cursor" "$log" | tail -5
+    return 1
+}
+
+status=0
+run_case 1 "Cursor on hardware plane" || status=1
+run_case 0 "Cursor composited" || status=1
+modprobe -r vkms 2>/dev/null
+exit $status